@@ -0,0 +1,250 @@
+use Color;
+use Film;
+use Filter;
+use Point;
+use NO_COLOR;
+
+/// Extra passes written next to the beauty image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    /// Colour of the first surface hit
+    Albedo,
+    /// Shading normal of the first hit, raw -1..1 values
+    Normal,
+    /// Distance from the camera to the first hit, 0 for the sky
+    Depth,
+    Position,
+    /// Random colour per object, instances count as separate objects
+    ObjectId,
+    /// Random colour per kind of material
+    MaterialId,
+    /// Light that reaches the first surface straight from the sky
+    Direct,
+    /// Everything that bounced more than once
+    Indirect,
+}
+
+pub const ALL_AOVS: [Aov; 8] = [
+    Aov::Albedo,
+    Aov::Normal,
+    Aov::Depth,
+    Aov::Position,
+    Aov::ObjectId,
+    Aov::MaterialId,
+    Aov::Direct,
+    Aov::Indirect,
+];
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        ALL_AOVS.iter().find(|a| a.name() == name).cloned()
+    }
+
+    /// Light passes go through exposure and tone mapping like the beauty
+    pub fn is_radiance(&self) -> bool {
+        *self == Aov::Direct || *self == Aov::Indirect
+    }
+}
+
+pub fn point_color(p: &Point) -> Color {
+    Color {
+        r: p.x,
+        g: p.y,
+        b: p.z,
+    }
+}
+
+/// Stable, well spread colour for an ID
+pub fn id_color(id: usize) -> Color {
+    let mut h = (id as u32).wrapping_add(1).wrapping_mul(0x9E37_79B9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85EB_CA6B);
+    h ^= h >> 13;
+    Color {
+        r: (h & 0xFF) as f32 / 255.0,
+        g: ((h >> 8) & 0xFF) as f32 / 255.0,
+        b: ((h >> 16) & 0xFF) as f32 / 255.0,
+    }
+}
+
+/// Every pass for one camera sample
+#[derive(Debug, Clone, Copy)]
+pub struct AovSample {
+    pub albedo: Color,
+    pub normal: Point,
+    pub depth: f32,
+    pub position: Point,
+    pub object_id: Color,
+    pub material_id: Color,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl AovSample {
+    /// A ray that hit nothing, `sky` counts as direct light
+    pub fn background(sky: Color) -> AovSample {
+        let zero = Point {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        AovSample {
+            albedo: NO_COLOR,
+            normal: zero,
+            depth: 0.0,
+            position: zero,
+            object_id: NO_COLOR,
+            material_id: NO_COLOR,
+            direct: sky,
+            indirect: NO_COLOR,
+        }
+    }
+
+    pub fn get(&self, aov: Aov) -> Color {
+        match aov {
+            Aov::Albedo => self.albedo,
+            Aov::Normal => point_color(&self.normal),
+            Aov::Depth => Color {
+                r: self.depth,
+                g: self.depth,
+                b: self.depth,
+            },
+            Aov::Position => point_color(&self.position),
+            Aov::ObjectId => self.object_id,
+            Aov::MaterialId => self.material_id,
+            Aov::Direct => self.direct,
+            Aov::Indirect => self.indirect,
+        }
+    }
+}
+
+/// The beauty film plus a film for each requested pass
+#[derive(Clone)]
+pub struct Layers {
+    pub beauty: Film,
+    pub aovs: Vec<(Aov, Film)>,
+}
+
+impl Layers {
+    pub fn new(width: usize, height: usize, aovs: &[Aov]) -> Layers {
+        Layers {
+            beauty: Film::new(width, height),
+            aovs: aovs
+                .iter()
+                .map(|a| (*a, Film::new(width, height)))
+                .collect(),
+        }
+    }
+
+    pub fn add_aov_sample(&mut self, x: f32, y: f32, sample: &AovSample, filter: &Filter) {
+        let beauty = sample.direct + sample.indirect;
+        self.beauty.add_sample(x, y, beauty, filter);
+        for (aov, film) in self.aovs.iter_mut() {
+            film.add_sample(x, y, sample.get(*aov), filter);
+        }
+    }
+
+    /// Adds in layers rendered for a band of rows starting at row `y`
+    pub fn merge_at(&mut self, other: &Layers, y: i64) {
+        self.beauty.merge_at(&other.beauty, y);
+        for ((_, a), (_, b)) in self.aovs.iter_mut().zip(other.aovs.iter()) {
+            a.merge_at(b, y);
+        }
+    }
+
+    pub fn get(&self, aov: Aov) -> Option<&Film> {
+        self.aovs.iter().find(|(a, _)| *a == aov).map(|(_, f)| f)
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn grey(v: f32) -> Color {
+        Color { r: v, g: v, b: v }
+    }
+
+    #[allow(dead_code)]
+    fn sample() -> AovSample {
+        AovSample {
+            albedo: grey(0.1),
+            normal: Point {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            depth: 7.0,
+            position: Point {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            object_id: grey(0.4),
+            material_id: grey(0.5),
+            direct: grey(0.6),
+            indirect: grey(0.7),
+        }
+    }
+
+    #[test]
+    fn test_each_pass_reads_its_field() {
+        let s = sample();
+        let expected = [
+            (Aov::Albedo, 0.1, 0.1, 0.1),
+            (Aov::Normal, 0.0, 1.0, 0.0),
+            (Aov::Depth, 7.0, 7.0, 7.0),
+            (Aov::Position, 1.0, 2.0, 3.0),
+            (Aov::ObjectId, 0.4, 0.4, 0.4),
+            (Aov::MaterialId, 0.5, 0.5, 0.5),
+            (Aov::Direct, 0.6, 0.6, 0.6),
+            (Aov::Indirect, 0.7, 0.7, 0.7),
+        ];
+        assert_eq!(expected.len(), ALL_AOVS.len());
+        for (aov, r, g, b) in &expected {
+            let c = s.get(*aov);
+            assert!((c.r - r).abs() < 1e-6 && (c.g - g).abs() < 1e-6 && (c.b - b).abs() < 1e-6);
+            assert_eq!(Aov::from_name(aov.name()), Some(*aov));
+        }
+    }
+
+    #[test]
+    fn test_ids_are_stable_and_distinct() {
+        let a = id_color(3);
+        assert!((a - id_color(3)).length() == 0.0);
+        for other in &[0, 1, 2, 4, 1000] {
+            assert!((a - id_color(*other)).length() > 0.05);
+        }
+    }
+
+    #[test]
+    fn test_band_lines_up_with_the_beauty() {
+        let aovs = [Aov::Depth, Aov::Direct];
+        let mut image = Layers::new(3, 4, &aovs);
+        let mut band = Layers::new(3, 2, &aovs);
+        band.add_aov_sample(1.5, 0.5, &sample(), &Filter::box_filter());
+        image.merge_at(&band, 2);
+        assert!((image.beauty.get(1, 2).r - 1.3).abs() < 1e-5);
+        assert!((image.get(Aov::Depth).unwrap().get(1, 2).r - 7.0).abs() < 1e-5);
+        assert!((image.get(Aov::Direct).unwrap().get(1, 2).r - 0.6).abs() < 1e-5);
+        for aov in &aovs {
+            assert_eq!(image.get(*aov).unwrap().get(1, 1).r, 0.0);
+            assert_eq!(image.get(*aov).unwrap().get(1, 3).r, 0.0);
+        }
+        assert!(image.get(Aov::Albedo).is_none());
+    }
+}
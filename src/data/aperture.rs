@@ -0,0 +1,124 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rnd;
+use Point;
+
+/// Shape of the lens opening. Out of focus highlights take this shape.
+/// Samples are points in the unit disk (z = 0) scaled by the lens radius.
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    /// Regular polygon with `blades` sides, rotated by `rotation` degrees
+    Polygon { blades: u32, rotation: f32 },
+    Mask(Arc<ApertureMask>),
+}
+
+fn random_in_unit_disk() -> Point {
+    loop {
+        let p = Point {
+            x: rnd() * 2.0 - 1.0,
+            y: rnd() * 2.0 - 1.0,
+            z: 0.0,
+        };
+        if p.dot(&p) < 1.0 {
+            return p;
+        }
+    }
+}
+
+fn random_in_polygon(blades: u32, rotation: f32) -> Point {
+    // Every triangle between the centre and a side has the same area so pick
+    // one at random then a uniform point inside it
+    let step = 2.0 * PI / blades as f32;
+    let start = rotation * PI / 180.0 + (rnd() * blades as f32).floor() * step;
+    let (a, b) = (rnd(), rnd());
+    let (a, b) = if a + b > 1.0 { (1.0 - a, 1.0 - b) } else { (a, b) };
+    Point {
+        x: a * start.cos() + b * (start + step).cos(),
+        y: a * start.sin() + b * (start + step).sin(),
+        z: 0.0,
+    }
+}
+
+impl Aperture {
+    pub fn sample(&self) -> Point {
+        match self {
+            Aperture::Circle => random_in_unit_disk(),
+            Aperture::Polygon { blades, rotation } => random_in_polygon(*blades, *rotation),
+            Aperture::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+/// Greyscale image of the aperture, brighter pixels let more light in. The
+/// image is stretched over the square around the unit disk.
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    cdf: Vec<f32>,
+}
+
+impl ApertureMask {
+    pub fn open(path: &str) -> Result<ApertureMask, String> {
+        let img = image::open(path).map_err(|e| e.to_string())?;
+        ApertureMask::from_image(&img.to_luma8())
+    }
+
+    /// Fails if no light would get through at all
+    pub fn from_image(img: &image::GrayImage) -> Result<ApertureMask, String> {
+        let (width, height) = img.dimensions();
+        let mut total = 0.0;
+        let mut cdf = Vec::with_capacity((width * height) as usize);
+        for pixel in img.pixels() {
+            total += f32::from(pixel.0[0]) / 255.0;
+            cdf.push(total);
+        }
+        if total <= 0.0 {
+            return Err("the mask is completely black".to_string());
+        }
+        Ok(ApertureMask { width, height, cdf })
+    }
+
+    pub fn sample(&self) -> Point {
+        let target = rnd() * self.cdf[self.cdf.len() - 1];
+        let i = match self
+            .cdf
+            .binary_search_by(|c| c.partial_cmp(&target).unwrap())
+        {
+            Ok(i) | Err(i) => i.min(self.cdf.len() - 1),
+        };
+        let x = (i as u32 % self.width) as f32 + rnd();
+        let y = (i as u32 / self.width) as f32 + rnd();
+        Point {
+            x: x / self.width as f32 * 2.0 - 1.0,
+            // Image rows go down, the lens v axis goes up
+            y: 1.0 - y / self.height as f32 * 2.0,
+            z: 0.0,
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_polygon_inside_disk() {
+        let aperture = Aperture::Polygon {
+            blades: 5,
+            rotation: 18.0,
+        };
+        for _ in 0..1000 {
+            let p = aperture.sample();
+            assert!(p.dot(&p) <= 1.0001);
+        }
+    }
+
+    #[test]
+    fn test_black_mask_is_an_error() {
+        assert!(ApertureMask::from_image(&image::GrayImage::new(4, 4)).is_err());
+        let open = image::GrayImage::from_pixel(4, 4, image::Luma([255]));
+        assert!(ApertureMask::from_image(&open).is_ok());
+    }
+}
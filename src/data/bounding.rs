@@ -1,5 +1,6 @@
 use std::cmp::Ordering::Equal;
 use std::f32;
+use std::sync::Arc;
 
 use rnd;
 use Hit;
@@ -7,6 +8,7 @@ use Hittable;
 use Point;
 use Ray;
 use SphereThing;
+use Affine;
 
 #[derive(Clone)]
 pub enum BvhBox {
@@ -25,6 +27,8 @@ pub struct BvhNode {
 pub struct BvhLeaf {
     pub boxx: BoundingBox,
     pub has_a: SphereThing,
+    /// Copied onto every hit so passes can tell objects apart
+    pub id: usize,
 }
 
 // pub enum HitResult {
@@ -38,7 +42,7 @@ pub struct PossibleHit<'a> {
     pub t: f32,
 }
 
-fn surrounding_box(a: &BoundingBox, b: &BoundingBox) -> BoundingBox {
+pub fn surrounding_box(a: &BoundingBox, b: &BoundingBox) -> BoundingBox {
     let p1 = Point {
         x: a.point1.x.min(b.point1.x),
         y: a.point1.y.min(b.point1.y),
@@ -72,7 +76,7 @@ fn get_t_or_max(hit: &Option<Hit>) -> f32 {
     }
 }
 
-fn process_hit<'a>(hit: PossibleHit<'a>, deepest_hit: f32, r: &Ray) -> Option<Hit> {
+fn process_hit<'a>(hit: PossibleHit<'a>, deepest_hit: f32, r: &Ray) -> Option<Hit<'a>> {
     if hit.t > deepest_hit {
         None
     } else {
@@ -85,7 +89,7 @@ fn process_double_hit<'a>(
     hit2: PossibleHit<'a>,
     deepest_hit: f32,
     r: &Ray,
-) -> Option<Hit> {
+) -> Option<Hit<'a>> {
     if hit.t > deepest_hit {
         None
     } else {
@@ -114,7 +118,7 @@ impl BvhNode {
     pub fn hit<'a>(&'a self, the_enum: &'a BvhBox, r: &Ray) -> Option<PossibleHit<'a>> {
         hit_bvh(the_enum, r)
     }
-    pub fn dig<'a>(&'a self, r: &Ray, deepest_hit: f32) -> Option<Hit> {
+    pub fn dig<'a>(&'a self, r: &Ray, deepest_hit: f32) -> Option<Hit<'a>> {
         let left_hit: Option<PossibleHit<'a>> = self.left.hit(r);
         let right_hit: Option<PossibleHit<'a>> = self.right.hit(r);
         match (left_hit, right_hit) {
@@ -136,11 +140,10 @@ impl BvhLeaf {
     pub fn hit<'a>(&'a self, the_enum: &'a BvhBox, r: &Ray) -> Option<PossibleHit<'a>> {
         hit_bvh(the_enum, r)
     }
-    pub fn dig(&self, r: &Ray) -> Option<Hit> {
-        match self.has_a.hit(r, 0.0001, f32::MAX) {
-            Some(h) => Some(h),
-            None => None,
-        }
+    pub fn dig(&self, r: &Ray) -> Option<Hit<'_>> {
+        self.has_a
+            .hit(r, 0.0001, f32::MAX)
+            .map(|h| Hit { object_id: self.id, ..h })
     }
     pub fn get_box(&self) -> &BoundingBox {
         &self.boxx
@@ -155,7 +158,7 @@ impl BvhBox {
         }
     }
 
-    pub fn dig(&self, r: &Ray, deepest_hit: f32) -> Option<Hit> {
+    pub fn dig(&self, r: &Ray, deepest_hit: f32) -> Option<Hit<'_>> {
         match self {
             BvhBox::Leaf(leaf) => leaf.dig(r),
             BvhBox::Node(node) => node.dig(r, deepest_hit),
@@ -201,6 +204,50 @@ pub fn get_bvh_box(spheres: &mut [BvhLeaf]) -> BvhBox {
     }
 }
 
+pub fn spheres_to_bounding_box(spheres: Vec<SphereThing>) -> BvhBox {
+    let mut bounds = vec![];
+    for (id, a) in spheres.into_iter().enumerate() {
+        let b = a.bounding_box();
+        bounds.push(BvhLeaf {
+            boxx: b,
+            has_a: a,
+            id,
+        });
+    }
+    get_bvh_box(&mut bounds)
+}
+
+/// A placement of a shared bottom level BVH. A BVH built over instances is
+/// the top level: its leaves hold these and the ray is moved into object
+/// space at the boundary, so the shared geometry is never duplicated.
+#[derive(Clone)]
+pub struct Instance {
+    pub affine: Affine,
+    pub blas: Arc<BvhBox>,
+}
+
+impl Instance {
+    pub fn new(blas: &Arc<BvhBox>, affine: Affine) -> Instance {
+        Instance {
+            affine,
+            blas: Arc::clone(blas),
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let local = self.affine.ray_to_object(r);
+        self.blas
+            .dig(&local, t_max)
+            .filter(|h| h.t > t_min && h.t < t_max)
+            .map(|h| self.affine.hit_to_world(h))
+    }
+    fn bounding_box(&self) -> BoundingBox {
+        self.affine.box_to_world(self.blas.get_box())
+    }
+}
+
 #[derive(Clone)]
 pub struct BoundingBox {
     pub point1: Point,
@@ -259,6 +306,7 @@ mod tests {
                 z: 0.000001,
             },
             time: 0.0,
+            wavelength: 0.0,
         };
         // Fire ray up
         let r_miss_y = Ray {
@@ -273,6 +321,7 @@ mod tests {
                 z: 0.000001,
             },
             time: 0.0,
+            wavelength: 0.0,
         };
         // Fire ray forwards
         let r_miss_z = Ray {
@@ -287,6 +336,7 @@ mod tests {
                 z: 1.0,
             },
             time: 0.0,
+            wavelength: 0.0,
         };
         // Fire ray right but over box
         let r_miss_x = Ray {
@@ -301,6 +351,7 @@ mod tests {
                 z: 1.0,
             },
             time: 0.0,
+            wavelength: 0.0,
         };
         // Fire angled ray
         let r_hit_funny = Ray {
@@ -315,6 +366,7 @@ mod tests {
                 z: -1.5,
             },
             time: 0.0,
+            wavelength: 0.0,
         };
         assert!(bb.hit(&r_hit).is_some());
         assert!(bb.hit(&r_miss_y).is_none());
@@ -322,4 +374,52 @@ mod tests {
         assert!(bb.hit(&r_miss_x).is_none());
         assert!(bb.hit(&r_hit_funny).is_some());
     }
+
+    #[test]
+    fn test_instance_moves_blas() {
+        use Dielectric;
+        use Material;
+        use Sphere;
+        use NO_TRANSFORM;
+
+        let blas = Arc::new(spheres_to_bounding_box(vec![SphereThing::S(Sphere {
+            center: Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            radius: 1.0,
+            material: Material::Dielectric(Dielectric {
+                reflective_index: 1.5,
+                dispersion: None,
+            }),
+        })]));
+        let instance = Instance::new(
+            &blas,
+            NO_TRANSFORM.translate(Point {
+                x: 0.0,
+                y: 0.0,
+                z: -5.0,
+            }),
+        );
+        let r = Ray {
+            origin: Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            direction: Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            time: 0.0,
+            wavelength: 0.0,
+        };
+        let hit = instance.hit(&r, 0.0001, f32::MAX).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-4);
+        assert!((hit.point.z + 4.0).abs() < 1e-4);
+        assert!((hit.normal.z - 1.0).abs() < 1e-4);
+        assert!((instance.bounding_box().point1.z + 6.0).abs() < 1e-4);
+    }
 }
@@ -1,23 +1,24 @@
 
+use std::f32;
 use std::f32::consts::PI;
 use Ray;
 use Point;
-use rnd;
+use Shutter;
+use ShutterCurve;
+use Aperture;
+use BvhBox;
+use Exposure;
 
 
-fn random_in_unit_disk() -> Point {
-    loop {
-        let p = Point {
-            x: rnd() * 2.0 - 1.0,
-            y: rnd() * 2.0 - 1.0,
-            z: 0.0,
-        };
-        if p.dot(&p) < 1.0 {
-            return p;
-        }
-    }
+/// Anything that can turn a point on the image (s, t in 0..1, from the
+/// bottom left) into a ray. calc_pixel only talks to cameras through this.
+/// None means the point is outside what the lens can see (eg: the corners
+/// of a fisheye image) and stays black.
+pub trait CameraModel: Sync {
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray>;
 }
 
+/// Thin lens perspective camera
 pub struct Camera {
     origin: Point,
     lower_left: Point,
@@ -25,59 +26,642 @@ pub struct Camera {
     vertical: Point,
     u: Point,
     v: Point,
-    time1: f32,
-    time0: f32,
+    shutter: Shutter,
     lens_radius: f32,
+    aperture_shape: Aperture,
 }
 
-impl Camera {
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = random_in_unit_disk() * self.lens_radius;
+impl CameraModel for Camera {
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        let rd = self.aperture_shape.sample() * self.lens_radius;
         let offset = self.u * rd.x + self.v * rd.y;
-        let end = self.origin - offset;
-        let time = self.time0 + (rnd() * (self.time1 - self.time0));
-        let direction = self.lower_left + self.horizontal * s + self.vertical * t - end;
-        Ray {
-            origin: self.origin + offset,
+        let start = self.origin + offset;
+        let time = self.shutter.sample(t);
+        // Aim from the point on the lens the ray actually leaves, so every
+        // sample for (s, t) meets at the same spot on the focus plane
+        let direction = self.lower_left + self.horizontal * s + self.vertical * t - start;
+        Some(Ray {
+            origin: start,
             direction,
             time,
+            wavelength: 0.0,
+        })
+    }
+}
+
+/// Parallel rays leaving a `view_width` wide rectangle centred on look_from
+pub struct OrthographicCamera {
+    lower_left: Point,
+    horizontal: Point,
+    vertical: Point,
+    direction: Point,
+    shutter: Shutter,
+}
+
+impl CameraModel for OrthographicCamera {
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        Some(Ray {
+            origin: self.lower_left + self.horizontal * s + self.vertical * t,
+            direction: self.direction,
+            time: self.shutter.sample(t),
+            wavelength: 0.0,
+        })
+    }
+}
+
+/// Latitude-longitude panorama covering every direction. The centre of the
+/// image looks towards look_at, s spans 360 degrees and t spans 180.
+///
+/// A non zero `eye_offset` gives one eye of an omnidirectional stereo pair:
+/// each ray starts on a circle of that radius, to the side of its own
+/// viewing direction. The offset fades towards the poles so the two eyes
+/// meet there instead of swirling.
+pub struct EquirectangularCamera {
+    origin: Point,
+    u: Point,
+    v: Point,
+    w: Point,
+    eye_offset: f32,
+    shutter: Shutter,
+}
+
+impl CameraModel for EquirectangularCamera {
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        let phi = (s - 0.5) * 2.0 * PI;
+        let theta = (t - 0.5) * PI;
+        let direction = self.u * (theta.cos() * phi.sin()) + self.v * theta.sin()
+            - self.w * (theta.cos() * phi.cos());
+        let side = self.u * phi.cos() + self.w * phi.sin();
+        Some(Ray {
+            origin: self.origin + side * (self.eye_offset * theta.cos()),
+            direction,
+            time: self.shutter.sample(t),
+            wavelength: 0.0,
+        })
+    }
+}
+
+/// Equidistant fisheye: the angle from the view axis grows linearly with
+/// the distance from the image centre. The image circle fits the height
+/// of the image and spans `fov` degrees.
+pub struct FisheyeCamera {
+    origin: Point,
+    u: Point,
+    v: Point,
+    w: Point,
+    aspect: f32,
+    half_fov: f32,
+    shutter: Shutter,
+}
+
+impl CameraModel for FisheyeCamera {
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        let x = (s * 2.0 - 1.0) * self.aspect;
+        let y = t * 2.0 - 1.0;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = r * self.half_fov;
+        let phi = y.atan2(x);
+        let direction = self.u * (theta.sin() * phi.cos()) + self.v * (theta.sin() * phi.sin())
+            - self.w * theta.cos();
+        Some(Ray {
+            origin: self.origin,
+            direction,
+            time: self.shutter.sample(t),
+            wavelength: 0.0,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye on the left half of the image
+    SideBySide,
+    /// Left eye on the top half of the image
+    OverUnder,
+}
+
+/// Two cameras sharing one image
+pub struct StereoCamera {
+    left: Box<dyn CameraModel>,
+    right: Box<dyn CameraModel>,
+    layout: StereoLayout,
+}
+
+impl CameraModel for StereoCamera {
+    fn get_ray(&self, s: f32, t: f32) -> Option<Ray> {
+        match self.layout {
+            StereoLayout::SideBySide if s < 0.5 => self.left.get_ray(s * 2.0, t),
+            StereoLayout::SideBySide => self.right.get_ray(s * 2.0 - 1.0, t),
+            StereoLayout::OverUnder if t >= 0.5 => self.left.get_ray(s, t * 2.0 - 1.0),
+            StereoLayout::OverUnder => self.right.get_ray(s, t * 2.0),
         }
     }
 }
 
-pub fn get_camera(
+fn eye_aspect(aspect: f32, layout: StereoLayout) -> f32 {
+    match layout {
+        StereoLayout::SideBySide => aspect / 2.0,
+        StereoLayout::OverUnder => aspect * 2.0,
+    }
+}
+
+/// Named settings for a Camera. Anything not set keeps the default below.
+/// Focus defaults to the distance to `look_at` unless set explicitly or
+/// picked by `autofocus`.
+#[derive(Clone)]
+pub struct CameraBuilder {
     look_from: Point,
     look_at: Point,
     up: Point,
     vfov: f32,
     aspect: f32,
     aperture: f32,
-    time0: f32,
-    time1: f32,
-) -> Camera {
-    let focus_dist = (look_from - look_at).length();
-    let lens_radius = aperture / 2.0;
-    let theta = vfov * PI / 180.0;
-    let half_height = f32::tan(theta / 2.0);
-    let half_width = aspect * half_height;
-    let w = (look_from - look_at).unit_vector();
-    let u = (up.cross(&w)).unit_vector();
-    let v = w.cross(&u);
-
-    let lower_left =
-        look_from - (u * half_width * focus_dist) - (v * half_height * focus_dist) - w * focus_dist;
-    let horizontal = u * (2.0 * focus_dist * half_width);
-    let vertical = v * (2.0 * focus_dist * half_height);
-
-    Camera {
-        lower_left,
-        horizontal,
-        vertical,
-        origin: look_from,
-        lens_radius,
-        u,
-        v,
-        time0,
-        time1,
-    }
-}
\ No newline at end of file
+    aperture_shape: Aperture,
+    f_stop: Option<f32>,
+    focus_dist: Option<f32>,
+    shutter: Shutter,
+}
+
+impl Default for CameraBuilder {
+    fn default() -> CameraBuilder {
+        CameraBuilder {
+            look_from: Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            look_at: Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            up: Point {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            vfov: 90.0,
+            aspect: 2.0,
+            aperture: 0.0,
+            aperture_shape: Aperture::Circle,
+            f_stop: None,
+            focus_dist: None,
+            shutter: Shutter {
+                time0: 0.0,
+                time1: 1.0,
+                curve: ShutterCurve::Box,
+                readout: None,
+            },
+        }
+    }
+}
+
+impl CameraBuilder {
+    pub fn new() -> CameraBuilder {
+        CameraBuilder::default()
+    }
+
+    pub fn look_from(mut self, look_from: Point) -> CameraBuilder {
+        self.look_from = look_from;
+        self
+    }
+    pub fn look_at(mut self, look_at: Point) -> CameraBuilder {
+        self.look_at = look_at;
+        self
+    }
+    pub fn up(mut self, up: Point) -> CameraBuilder {
+        self.up = up;
+        self
+    }
+    /// Vertical field of view in degrees
+    pub fn vfov(mut self, vfov: f32) -> CameraBuilder {
+        self.vfov = vfov;
+        self
+    }
+    pub fn aspect(mut self, aspect: f32) -> CameraBuilder {
+        self.aspect = aspect;
+        self
+    }
+    pub fn aperture(mut self, aperture: f32) -> CameraBuilder {
+        self.aperture = aperture;
+        self
+    }
+    /// Sets the aperture from a real lens: `focal_length` in scene units
+    /// (0.05 for a 50mm lens in a scene measured in metres) and an f-number.
+    /// The same f-number is then used by `exposure`.
+    pub fn lens(mut self, focal_length: f32, f_stop: f32) -> CameraBuilder {
+        self.aperture = focal_length / f_stop;
+        self.f_stop = Some(f_stop);
+        self
+    }
+    pub fn aperture_shape(mut self, aperture_shape: Aperture) -> CameraBuilder {
+        self.aperture_shape = aperture_shape;
+        self
+    }
+    pub fn focus_dist(mut self, focus_dist: f32) -> CameraBuilder {
+        self.focus_dist = Some(focus_dist);
+        self
+    }
+    pub fn shutter(mut self, time0: f32, time1: f32) -> CameraBuilder {
+        self.shutter.time0 = time0;
+        self.shutter.time1 = time1;
+        self
+    }
+    pub fn shutter_curve(mut self, curve: ShutterCurve) -> CameraBuilder {
+        self.shutter.curve = curve;
+        self
+    }
+    /// Time between the top and bottom rows starting their exposure
+    pub fn rolling_shutter(mut self, readout: f32) -> CameraBuilder {
+        self.shutter.readout = Some(readout);
+        self
+    }
+
+    /// Exposure for the lens set with `lens`, f/16 if there isn't one
+    pub fn exposure(&self, iso: f32, shutter_speed: f32, compensation: f32) -> Exposure {
+        Exposure {
+            iso,
+            shutter_speed,
+            f_stop: self.f_stop.unwrap_or(16.0),
+            compensation,
+        }
+    }
+
+    /// Focus on whatever is seen through `pixel` of an `image` sized render.
+    /// Pixels are counted the way the renderer counts them: x from the left
+    /// and y up from the bottom row, so (0, 0) is the bottom left corner.
+    /// If the ray escapes the scene the focus distance is left alone.
+    pub fn autofocus(self, pixel: (i32, i32), image: (i32, i32), world: &BvhBox) -> CameraBuilder {
+        let pinhole = self.clone().aperture(0.0).focus_dist(1.0).build();
+        let ray = pinhole
+            .get_ray(
+                (pixel.0 as f32 + 0.5) / image.0 as f32,
+                (pixel.1 as f32 + 0.5) / image.1 as f32,
+            ).unwrap();
+        match world.dig(&ray, f32::MAX) {
+            Some(hit) => {
+                let forward = (self.look_at - self.look_from).unit_vector();
+                let focus_dist = (hit.point - self.look_from).dot(&forward);
+                self.focus_dist(focus_dist)
+            }
+            None => self,
+        }
+    }
+
+    /// Right, up and backwards unit vectors of the view
+    fn basis(&self) -> (Point, Point, Point) {
+        let w = (self.look_from - self.look_at).unit_vector();
+        let u = (self.up.cross(&w)).unit_vector();
+        let v = w.cross(&u);
+        (u, v, w)
+    }
+
+    pub fn build(&self) -> Camera {
+        let focus_dist = self
+            .focus_dist
+            .unwrap_or_else(|| (self.look_from - self.look_at).length());
+        let lens_radius = self.aperture / 2.0;
+        let theta = self.vfov * PI / 180.0;
+        let half_height = f32::tan(theta / 2.0);
+        let half_width = self.aspect * half_height;
+        let (u, v, w) = self.basis();
+
+        let lower_left = self.look_from
+            - (u * half_width * focus_dist)
+            - (v * half_height * focus_dist)
+            - w * focus_dist;
+        let horizontal = u * (2.0 * focus_dist * half_width);
+        let vertical = v * (2.0 * focus_dist * half_height);
+
+        Camera {
+            lower_left,
+            horizontal,
+            vertical,
+            origin: self.look_from,
+            lens_radius,
+            aperture_shape: self.aperture_shape.clone(),
+            u,
+            v,
+            shutter: self.shutter.clone(),
+        }
+    }
+
+    /// Uses the same placement as `build` but ignores vfov and the lens.
+    /// The view height follows from the aspect ratio.
+    pub fn build_orthographic(&self, view_width: f32) -> OrthographicCamera {
+        let (u, v, w) = self.basis();
+        let horizontal = u * view_width;
+        let vertical = v * (view_width / self.aspect);
+
+        OrthographicCamera {
+            lower_left: self.look_from - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            direction: w * -1.0,
+            shutter: self.shutter.clone(),
+        }
+    }
+
+    /// Full sphere panorama from look_from. Use a 2:1 image.
+    pub fn build_equirectangular(&self) -> EquirectangularCamera {
+        let (u, v, w) = self.basis();
+        EquirectangularCamera {
+            origin: self.look_from,
+            u,
+            v,
+            w,
+            eye_offset: 0.0,
+            shutter: self.shutter.clone(),
+        }
+    }
+
+    /// `fov` is the angle across the image circle in degrees, up to 360
+    pub fn build_fisheye(&self, fov: f32) -> FisheyeCamera {
+        let (u, v, w) = self.basis();
+        FisheyeCamera {
+            origin: self.look_from,
+            u,
+            v,
+            w,
+            aspect: self.aspect,
+            half_fov: fov * PI / 360.0,
+            shutter: self.shutter.clone(),
+        }
+    }
+
+    /// Eyes `ipd` apart with parallel view axes. With a convergence distance
+    /// each eye's image window is shifted (not toed in) so objects at that
+    /// distance line up in both eyes, without any vertical parallax.
+    pub fn build_stereo(
+        &self,
+        ipd: f32,
+        convergence: Option<f32>,
+        layout: StereoLayout,
+    ) -> StereoCamera {
+        let (u, _, _) = self.basis();
+        let focus_dist = self
+            .focus_dist
+            .unwrap_or_else(|| (self.look_from - self.look_at).length());
+        let eye = |side: f32| -> Box<dyn CameraModel> {
+            let offset = u * (side * ipd / 2.0);
+            let mut cam = self
+                .clone()
+                .look_from(self.look_from + offset)
+                .look_at(self.look_at + offset)
+                .focus_dist(focus_dist)
+                .aspect(eye_aspect(self.aspect, layout))
+                .build();
+            if let Some(distance) = convergence {
+                cam.lower_left = cam.lower_left - offset * (focus_dist / distance);
+            }
+            Box::new(cam)
+        };
+        StereoCamera {
+            left: eye(-1.0),
+            right: eye(1.0),
+            layout,
+        }
+    }
+
+    /// Omnidirectional stereo panorama. Over-under with a square image gives
+    /// the usual 2:1 picture per eye.
+    pub fn build_equirectangular_stereo(&self, ipd: f32, layout: StereoLayout) -> StereoCamera {
+        let eye = |side: f32| -> Box<dyn CameraModel> {
+            Box::new(EquirectangularCamera {
+                eye_offset: side * ipd / 2.0,
+                ..self.build_equirectangular()
+            })
+        };
+        StereoCamera {
+            left: eye(-1.0),
+            right: eye(1.0),
+            layout,
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_autofocus_counts_rows_from_the_bottom() {
+        use spheres_to_bounding_box;
+        use Dielectric;
+        use Material;
+        use Sphere;
+        use SphereThing;
+
+        let glass = || {
+            Material::Dielectric(Dielectric {
+                reflective_index: 1.5,
+                dispersion: None,
+            })
+        };
+        // One sphere above the axis and a nearer one mirrored below it, so
+        // counting rows from the top would focus on the wrong one
+        let world = spheres_to_bounding_box(vec![
+            SphereThing::S(Sphere {
+                center: Point {
+                    x: 2.0,
+                    y: 1.0,
+                    z: -6.0,
+                },
+                radius: 0.5,
+                material: glass(),
+            }),
+            SphereThing::S(Sphere {
+                center: Point {
+                    x: 1.0,
+                    y: -0.5,
+                    z: -3.0,
+                },
+                radius: 0.25,
+                material: glass(),
+            }),
+        ]);
+        // With a 90 degree view the upper sphere's centre lands 7/12 of the
+        // way across and 7/12 of the way up a 200x100 image
+        let builder = CameraBuilder::default()
+            .vfov(90.0)
+            .aspect(2.0)
+            .autofocus((116, 58), (200, 100), &world);
+        let focus = builder.focus_dist.unwrap();
+        assert!((focus - 5.5).abs() < 0.1, "focused at {}", focus);
+    }
+
+    #[test]
+    fn test_lens_samples_meet_on_the_focus_plane() {
+        let camera = CameraBuilder::default()
+            .aperture(1.0)
+            .focus_dist(4.0)
+            .build();
+        let first = camera.get_ray(0.3, 0.6).unwrap();
+        let focus = first.origin + first.direction;
+        for _ in 0..100 {
+            let r = camera.get_ray(0.3, 0.6).unwrap();
+            assert!((r.origin + r.direction - focus).length() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_orthographic_rays_are_parallel() {
+        let look_from = Point {
+            x: 1.0,
+            y: 2.0,
+            z: 0.0,
+        };
+        let look_at = Point {
+            x: 4.0,
+            y: 3.0,
+            z: -2.0,
+        };
+        let forward = (look_at - look_from).unit_vector();
+        let camera = CameraBuilder::default()
+            .look_from(look_from)
+            .look_at(look_at)
+            .aspect(2.0)
+            .build_orthographic(4.0);
+        let ray = |s: f32, t: f32| camera.get_ray(s, t).unwrap();
+        for &(s, t) in &[(0.0, 0.0), (0.3, 0.8), (1.0, 1.0)] {
+            let r = ray(s, t);
+            assert!((r.direction.unit_vector().dot(&forward) - 1.0).abs() < 1e-5);
+            // Every ray starts in the plane through look_from
+            assert!((r.origin - look_from).dot(&forward).abs() < 1e-5);
+        }
+        assert!(((ray(1.0, 0.5).origin - ray(0.0, 0.5).origin).length() - 4.0).abs() < 1e-5);
+        assert!(((ray(0.5, 1.0).origin - ray(0.5, 0.0).origin).length() - 2.0).abs() < 1e-5);
+        assert!((ray(0.5, 0.5).origin - look_from).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_equirectangular_centre_and_pole() {
+        let look_at = Point {
+            x: 1.0,
+            y: 0.0,
+            z: -1.0,
+        };
+        let camera = CameraBuilder::default()
+            .look_at(look_at)
+            .build_equirectangular();
+        let direction = |s: f32, t: f32| camera.get_ray(s, t).unwrap().direction.unit_vector();
+        assert!((direction(0.5, 0.5).dot(&look_at.unit_vector()) - 1.0).abs() < 1e-5);
+        assert!((direction(0.3, 1.0).y - 1.0).abs() < 1e-5);
+        assert!((direction(0.7, 0.0).y + 1.0).abs() < 1e-5);
+        // Both edges of the image look straight back
+        assert!((direction(0.0, 0.5).dot(&look_at.unit_vector()) + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_fisheye_image_circle() {
+        let camera = CameraBuilder::default().aspect(2.0).build_fisheye(120.0);
+        let forward = Point {
+            x: 0.0,
+            y: 0.0,
+            z: -1.0,
+        };
+        let angle = |s: f32, t: f32| {
+            let d = camera.get_ray(s, t).unwrap().direction.unit_vector();
+            d.dot(&forward).min(1.0).acos().to_degrees()
+        };
+        assert!(angle(0.5, 0.5) < 1e-3);
+        // The circle touches the top and bottom, and is as wide as it is high
+        assert!((angle(0.5, 1.0) - 60.0).abs() < 1e-3);
+        assert!((angle(0.75, 0.5) - 60.0).abs() < 1e-3);
+        assert!((angle(0.5, 0.75) - 30.0).abs() < 1e-3);
+        for &(s, t) in &[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.8, 0.5)] {
+            assert!(camera.get_ray(s, t).is_none());
+        }
+    }
+
+    /// Where `ray` crosses the plane z = `z`
+    #[allow(dead_code)]
+    fn cross_plane(ray: &Ray, z: f32) -> Point {
+        ray.origin + ray.direction * ((z - ray.origin.z) / ray.direction.z)
+    }
+
+    #[test]
+    fn test_stereo_converges_without_toe_in() {
+        let stereo =
+            CameraBuilder::default().build_stereo(0.5, Some(5.0), StereoLayout::SideBySide);
+        let left = |s: f32, t: f32| stereo.get_ray(s * 0.5, t).unwrap();
+        let right = |s: f32, t: f32| stereo.get_ray(0.5 + s * 0.5, t).unwrap();
+        // Invert the (axis aligned) mapping from the image to the plane
+        let image_point = |eye: &dyn Fn(f32, f32) -> Ray, p: &Point| {
+            let at = |s: f32, t: f32| cross_plane(&eye(s, t), p.z);
+            let corner = at(0.0, 0.0);
+            (
+                (p.x - corner.x) / (at(0.5, 0.0).x - corner.x) * 0.5,
+                (p.y - corner.y) / (at(0.0, 0.5).y - corner.y) * 0.5,
+            )
+        };
+        for p in &[
+            Point {
+                x: 0.0,
+                y: 0.0,
+                z: -5.0,
+            },
+            Point {
+                x: 0.8,
+                y: -0.3,
+                z: -5.0,
+            },
+        ] {
+            let (ls, lt) = image_point(&left, p);
+            let (rs, rt) = image_point(&right, p);
+            assert!((ls - rs).abs() < 1e-4 && (lt - rt).abs() < 1e-4);
+        }
+        // Nearer than the convergence distance the eyes disagree sideways
+        // but, with parallel axes, never vertically
+        let near = Point {
+            x: 0.4,
+            y: 0.6,
+            z: -2.0,
+        };
+        let (ls, lt) = image_point(&left, &near);
+        let (rs, rt) = image_point(&right, &near);
+        assert!(ls > rs + 0.01);
+        assert!((lt - rt).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_stereo_layouts_pick_the_eye() {
+        let builder = CameraBuilder::default();
+        let side = builder.build_stereo(0.5, None, StereoLayout::SideBySide);
+        assert!((side.get_ray(0.25, 0.5).unwrap().origin.x + 0.25).abs() < 1e-5);
+        assert!((side.get_ray(0.75, 0.5).unwrap().origin.x - 0.25).abs() < 1e-5);
+        let over = builder.build_stereo(0.5, None, StereoLayout::OverUnder);
+        assert!((over.get_ray(0.5, 0.75).unwrap().origin.x + 0.25).abs() < 1e-5);
+        assert!((over.get_ray(0.5, 0.25).unwrap().origin.x - 0.25).abs() < 1e-5);
+        // Each half is a whole image: its centre looks straight ahead
+        for r in &[side.get_ray(0.25, 0.5), over.get_ray(0.5, 0.25)] {
+            let d = r.as_ref().unwrap().direction.unit_vector();
+            assert!((d.z + 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_ods_eyes_sit_beside_each_ray() {
+        let ods =
+            CameraBuilder::default().build_equirectangular_stereo(0.5, StereoLayout::OverUnder);
+        let offset = |s: f32, t: f32| {
+            let r = ods.get_ray(s, t).unwrap();
+            assert!(r.origin.dot(&r.direction.unit_vector()).abs() < 1e-5);
+            r.origin
+        };
+        for i in 0..20 {
+            let s = i as f32 / 20.0;
+            // Left eye on top, its horizon is three quarters of the way up
+            let left = offset(s, 0.75);
+            let right = offset(s, 0.25);
+            assert!((left.length() - 0.25).abs() < 1e-5);
+            assert!((left + right).length() < 1e-5);
+            // Shrinking towards the poles
+            assert!(offset(s, 0.95).length() < left.length() * 0.5);
+            assert!(offset(s, 1.0).length() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use Color;
+use Film;
+use NO_COLOR;
+
+// B3 spline, the 5 tap kernel from the a-trous paper
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Edge avoiding a-trous wavelet filter (Dammertz et al. 2010). Each pass
+/// blurs with a 5x5 kernel whose taps spread twice as far as the last,
+/// and a neighbour only counts if its colour, normal and depth are close.
+///
+/// Texture detail is kept by dividing the albedo out before filtering and
+/// multiplying it back in afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    pub iterations: u32,
+    /// How different colours can be, halved each pass
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    /// Relative to the depth of the centre pixel
+    pub sigma_depth: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            sigma_color: 1.0,
+            sigma_normal: 0.3,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+fn distance2(a: &Color, b: &Color) -> f32 {
+    let d = *a - *b;
+    d.r * d.r + d.g * d.g + d.b * d.b
+}
+
+// Black albedo (the sky, or a hole) would divide by zero: leave those alone
+fn safe_albedo(c: &Color) -> Color {
+    let fix = |v: f32| if v < 0.01 { 1.0 } else { v };
+    Color {
+        r: fix(c.r),
+        g: fix(c.g),
+        b: fix(c.b),
+    }
+}
+
+fn divide(a: &Color, b: &Color) -> Color {
+    Color {
+        r: a.r / b.r,
+        g: a.g / b.g,
+        b: a.b / b.b,
+    }
+}
+
+impl Denoiser {
+    pub fn denoise(&self, beauty: &Film, albedo: &Film, normal: &Film, depth: &Film) -> Film {
+        let (width, height) = (beauty.width, beauty.height);
+        let albedo: Vec<Color> = albedo.resolved().iter().map(safe_albedo).collect();
+        let normal = normal.resolved();
+        let depth: Vec<f32> = depth.resolved().iter().map(|c| c.r).collect();
+        let mut current: Vec<Color> = beauty.resolved()
+            .iter()
+            .zip(albedo.iter())
+            .map(|(c, a)| divide(c, a))
+            .collect();
+
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            let sigma_color = self.sigma_color / (1 << iteration) as f32;
+            let mut next = vec![NO_COLOR; width * height];
+            for y in 0..height as i64 {
+                for x in 0..width as i64 {
+                    let p = (y * width as i64 + x) as usize;
+                    let mut sum = NO_COLOR;
+                    let mut total = 0.0;
+                    for (ky, hy) in KERNEL.iter().enumerate() {
+                        for (kx, hx) in KERNEL.iter().enumerate() {
+                            let qx = x + (kx as i64 - 2) * step;
+                            let qy = y + (ky as i64 - 2) * step;
+                            if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                                continue;
+                            }
+                            let q = (qy * width as i64 + qx) as usize;
+                            let w_color = (-distance2(&current[p], &current[q])
+                                / (sigma_color * sigma_color))
+                                .exp();
+                            let w_normal = (-distance2(&normal[p], &normal[q])
+                                / (self.sigma_normal * self.sigma_normal))
+                                .exp();
+                            let d = (depth[p] - depth[q]) / depth[p].max(1e-3);
+                            let w_depth = (-d * d / (self.sigma_depth * self.sigma_depth)).exp();
+                            let w = hx * hy * w_color * w_normal * w_depth;
+                            sum += current[q] * w;
+                            total += w;
+                        }
+                    }
+                    // The centre tap always has weight so total can't be 0
+                    next[p] = sum / total;
+                }
+            }
+            current = next;
+        }
+
+        let pixels = current
+            .iter()
+            .zip(albedo.iter())
+            .map(|(c, a)| c.mul(a))
+            .collect();
+        Film::from_pixels(width, height, pixels)
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use rnd;
+
+    #[test]
+    fn test_smooths_noise_but_keeps_edges() {
+        let (width, height) = (32, 16);
+        let grey = |v: f32| Color { r: v, g: v, b: v };
+        let mut noisy = vec![];
+        let mut normals = vec![];
+        for _y in 0..height {
+            for x in 0..width {
+                // Two flat walls facing different ways, 0.2 and 0.8 bright
+                let base = if x < width / 2 { 0.2 } else { 0.8 };
+                noisy.push(grey(base + (rnd() - 0.5) * 0.2));
+                normals.push(if x < width / 2 { grey(1.0) } else { grey(-1.0) });
+            }
+        }
+        let beauty = Film::from_pixels(width, height, noisy);
+        let albedo = Film::from_pixels(width, height, vec![grey(1.0); width * height]);
+        let normal = Film::from_pixels(width, height, normals);
+        let depth = Film::from_pixels(width, height, vec![grey(5.0); width * height]);
+
+        let result = Denoiser::default().denoise(&beauty, &albedo, &normal, &depth);
+        let error = |film: &Film| -> f32 {
+            let mut e = 0.0;
+            for y in 0..height {
+                for x in 0..width {
+                    let base = if x < width / 2 { 0.2 } else { 0.8 };
+                    e += (film.get(x, y).r - base).abs();
+                }
+            }
+            e
+        };
+        assert!(error(&result) < error(&beauty) / 2.0);
+        // Nothing bleeds over the edge
+        assert!(result.get(width / 2 - 1, 8).r < 0.35);
+        assert!(result.get(width / 2, 8).r > 0.65);
+    }
+}
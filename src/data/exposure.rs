@@ -0,0 +1,61 @@
+use Film;
+
+/// EV100 of the "sunny 16" rule: f/16 at 1/100s and ISO 100. The sky in
+/// our scenes is treated as daylight so these settings leave the image as
+/// it is, and every stop away from them doubles or halves it.
+const SUNNY_16_EV: f32 = 14.643_856;
+
+/// Photographic exposure applied to the linear image before tone mapping
+#[derive(Debug, Clone, Copy)]
+pub struct Exposure {
+    pub iso: f32,
+    /// Seconds
+    pub shutter_speed: f32,
+    pub f_stop: f32,
+    /// Extra stops, + brightens
+    pub compensation: f32,
+}
+
+impl Default for Exposure {
+    fn default() -> Exposure {
+        Exposure {
+            iso: 100.0,
+            shutter_speed: 0.01,
+            f_stop: 16.0,
+            compensation: 0.0,
+        }
+    }
+}
+
+impl Exposure {
+    pub fn ev100(&self) -> f32 {
+        (self.f_stop * self.f_stop / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+
+    pub fn scale(&self) -> f32 {
+        2.0f32.powf(SUNNY_16_EV - self.ev100() + self.compensation)
+    }
+
+    pub fn apply(&self, film: &mut Film) {
+        film.scale(self.scale());
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_stops() {
+        let sunny = Exposure::default();
+        assert!((sunny.scale() - 1.0).abs() < 1e-4);
+
+        // One stop wider and one stop more compensation: four times brighter
+        let brighter = Exposure {
+            f_stop: 16.0 / 2.0f32.sqrt(),
+            compensation: 1.0,
+            ..sunny
+        };
+        assert!((brighter.scale() - 4.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,245 @@
+use Color;
+use NO_COLOR;
+
+/// Reconstruction filter used to spread each sample over nearby pixels.
+/// Weights are separable: weight(x) * weight(y).
+#[derive(Debug, Clone, Copy)]
+pub enum Filter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, alpha: f32 },
+    /// Mitchell-Netravali cubic, B = C = 1/3 is the usual choice
+    Mitchell { radius: f32, b: f32, c: f32 },
+}
+
+impl Filter {
+    pub fn box_filter() -> Filter {
+        Filter::Box { radius: 0.5 }
+    }
+    pub fn tent() -> Filter {
+        Filter::Tent { radius: 1.0 }
+    }
+    pub fn gaussian() -> Filter {
+        Filter::Gaussian {
+            radius: 1.5,
+            alpha: 2.0,
+        }
+    }
+    pub fn mitchell() -> Filter {
+        Filter::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. } => *radius,
+        }
+    }
+
+    fn weight_1d(&self, d: f32) -> f32 {
+        let d = d.abs();
+        match self {
+            Filter::Box { radius } => {
+                if d <= *radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Tent { radius } => (radius - d).max(0.0),
+            Filter::Gaussian { radius, alpha } => {
+                ((-alpha * d * d).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                // The cubic is defined over -2..2
+                let x = 2.0 * d / radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else if x < 2.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+}
+
+/// Linear RGB image built from weighted samples. Pixel (x, y) covers
+/// x..x+1, y..y+1 with y going up from the bottom row like the camera's t.
+#[derive(Clone)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    sum: Vec<Color>,
+    weight: Vec<f32>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        Film {
+            width,
+            height,
+            sum: vec![NO_COLOR; width * height],
+            weight: vec![0.0; width * height],
+        }
+    }
+
+    /// A film holding already resolved pixels, rows from the bottom up
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> Film {
+        assert_eq!(pixels.len(), width * height);
+        Film {
+            width,
+            height,
+            sum: pixels,
+            weight: vec![1.0; width * height],
+        }
+    }
+
+    /// Adds a sample at continuous pixel position (x, y) to every pixel whose
+    /// centre is within the filter's radius
+    pub fn add_sample(&mut self, x: f32, y: f32, color: Color, filter: &Filter) {
+        let r = filter.radius();
+        let x0 = (x - r - 0.5).ceil().max(0.0) as usize;
+        let y0 = (y - r - 0.5).ceil().max(0.0) as usize;
+        let x1 = ((x + r - 0.5).floor() as i64).min(self.width as i64 - 1);
+        let y1 = ((y + r - 0.5).floor() as i64).min(self.height as i64 - 1);
+        for py in y0 as i64..=y1 {
+            for px in x0 as i64..=x1 {
+                let w = filter.weight(px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+                if w != 0.0 {
+                    let i = py as usize * self.width + px as usize;
+                    self.sum[i] += color * w;
+                    self.weight[i] += w;
+                }
+            }
+        }
+    }
+
+    /// Adds in a film of the same width whose bottom row lands on row `y`.
+    /// Rows falling outside this film are dropped, the same as samples
+    /// splatted past the edge.
+    pub fn merge_at(&mut self, other: &Film, y: i64) {
+        assert_eq!(self.width, other.width);
+        for row in 0..other.height {
+            let to = y + row as i64;
+            if to < 0 || to >= self.height as i64 {
+                continue;
+            }
+            let from = row * self.width;
+            let to = to as usize * self.width;
+            for x in 0..self.width {
+                self.sum[to + x] += other.sum[from + x];
+                self.weight[to + x] += other.weight[from + x];
+            }
+        }
+    }
+
+    pub fn scale(&mut self, by: f32) {
+        for c in self.sum.iter_mut() {
+            *c = *c * by;
+        }
+    }
+
+    /// `width` by `height` pixels starting at (x, y), counted from the
+    /// bottom left
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Film {
+        assert!(x + width <= self.width && y + height <= self.height);
+        let mut result = Film::new(width, height);
+        for row in 0..height {
+            let from = (y + row) * self.width + x;
+            let to = row * width;
+            result.sum[to..to + width].clone_from_slice(&self.sum[from..from + width]);
+            result.weight[to..to + width].clone_from_slice(&self.weight[from..from + width]);
+        }
+        result
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        let i = y * self.width + x;
+        // Mitchell has negative lobes so the weight can be near zero either side
+        if self.weight[i].abs() > 1e-6 {
+            self.sum[i] / self.weight[i]
+        } else {
+            NO_COLOR
+        }
+    }
+
+    /// Filtered pixels in film order: bottom row first, the layout
+    /// `from_pixels` takes back
+    pub fn resolved(&self) -> Vec<Color> {
+        (0..self.width * self.height)
+            .map(|i| self.get(i % self.width, i / self.width))
+            .collect()
+    }
+
+    /// Filtered pixels in image order: top row first
+    pub fn pixels(&self) -> Vec<Color> {
+        let resolved = self.resolved();
+        resolved
+            .chunks(self.width)
+            .rev()
+            .flat_map(|row| row.iter().cloned())
+            .collect()
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_splat_merge_crop() {
+        let red = Color {
+            r: 1.0,
+            g: 0.0,
+            b: 0.0,
+        };
+        let mut a = Film::new(4, 3);
+        let mut b = Film::new(4, 3);
+        // Box only touches the pixel the sample lands in
+        a.add_sample(1.2, 2.7, red, &Filter::box_filter());
+        b.add_sample(1.8, 2.1, red * 3.0, &Filter::box_filter());
+        assert_eq!(a.get(0, 2).r, 0.0);
+        a.merge_at(&b, 0);
+        assert!((a.get(1, 2).r - 2.0).abs() < 1e-6);
+
+        // A one row band only touches the row it is merged onto
+        let mut band = Film::new(4, 1);
+        band.add_sample(3.5, 0.5, red, &Filter::box_filter());
+        let mut d = Film::new(4, 3);
+        d.merge_at(&band, 1);
+        d.merge_at(&band, 3);
+        assert!((d.get(3, 1).r - 1.0).abs() < 1e-6);
+        assert_eq!(d.get(3, 2).r, 0.0);
+
+        // A tent reaches the neighbours too
+        let mut c = Film::new(4, 3);
+        c.add_sample(1.6, 1.5, red, &Filter::tent());
+        assert!(c.get(2, 1).r > 0.0);
+        assert!(c.get(0, 1).r == 0.0);
+
+        let cropped = a.crop(1, 1, 2, 2);
+        assert_eq!(cropped.width, 2);
+        assert!((cropped.get(0, 1).r - 2.0).abs() < 1e-6);
+        // Top row comes first
+        assert!((cropped.pixels()[0].r - 2.0).abs() < 1e-6);
+    }
+}
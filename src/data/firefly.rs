@@ -0,0 +1,83 @@
+use Color;
+
+/// Ways to trade a little bias for less noise from rare, very bright paths
+/// (light that found its way through the glass). Both are off by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fireflies {
+    /// Cap on the radiance carried back by any bounce after the first
+    pub max_indirect: Option<f32>,
+    /// Drop camera samples this many standard deviations brighter than the
+    /// rest of their pixel
+    pub reject_sigmas: Option<f32>,
+}
+
+pub fn luminance(c: &Color) -> f32 {
+    0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b
+}
+
+/// Scales `c` down so no channel is over `max`, keeping its hue
+pub fn clamp_radiance(c: Color, max: f32) -> Color {
+    let brightest = c.r.max(c.g).max(c.b);
+    if brightest > max {
+        c * (max / brightest)
+    } else {
+        c
+    }
+}
+
+impl Fireflies {
+    pub fn clamp_indirect(&self, c: Color) -> Color {
+        match self.max_indirect {
+            Some(max) => clamp_radiance(c, max),
+            None => c,
+        }
+    }
+
+    /// Which of a pixel's samples to keep
+    pub fn keep(&self, samples: &[Color]) -> Vec<bool> {
+        let sigmas = match self.reject_sigmas {
+            Some(s) if samples.len() > 2 => s,
+            _ => return vec![true; samples.len()],
+        };
+        let n = samples.len() as f32;
+        let lum: Vec<f32> = samples.iter().map(luminance).collect();
+        let mean = lum.iter().sum::<f32>() / n;
+        let variance = lum.iter().map(|l| (l - mean) * (l - mean)).sum::<f32>() / n;
+        let limit = mean + sigmas * variance.sqrt();
+        lum.iter().map(|l| *l <= limit).collect()
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_clamp_keeps_hue() {
+        let c = clamp_radiance(
+            Color {
+                r: 40.0,
+                g: 20.0,
+                b: 10.0,
+            },
+            4.0,
+        );
+        assert!((c.r - 4.0).abs() < 1e-5 && (c.g - 2.0).abs() < 1e-5 && (c.b - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_rejects_the_firefly() {
+        let grey = |v: f32| Color { r: v, g: v, b: v };
+        let mut samples: Vec<Color> = (0..64).map(|i| grey(0.4 + (i % 4) as f32 * 0.05)).collect();
+        samples[10] = grey(500.0);
+
+        assert!(Fireflies::default().keep(&samples).iter().all(|k| *k));
+        let reject = Fireflies {
+            reject_sigmas: Some(3.0),
+            ..Fireflies::default()
+        };
+        let keep = reject.keep(&samples);
+        assert!(!keep[10]);
+        assert_eq!(keep.iter().filter(|k| **k).count(), 63);
+    }
+}
@@ -7,12 +7,31 @@ use SphereMoving;
 use Texture;
 use ConstantTexture;
 use Metal;
+use Conductor;
 use Lambertian;
 use Dielectric;
+use Dispersion;
+use RoughDielectric;
+use Principled;
+use Param;
+use Coated;
+use Mix;
+use OrenNayar;
 use Material;
 use SphereList;
 use build_image_texture;
 use build_noise;
+use Affine;
+use Transform;
+use NO_TRANSFORM;
+use Instance;
+use spheres_to_bounding_box;
+use std::sync::Arc;
+use Animated;
+use AnimatedTransform;
+use Keyframe;
+use Quaternion;
+use NO_ROTATION;
 
 use rnd;
 
@@ -68,6 +87,7 @@ pub fn get_old_spheres() -> SphereList {
                         g: 0.6,
                         b: 0.2,
                     },
+                    fuzz: 0.1,
                 }),
                 time0: 0.0,
                 time1: 1.0,
@@ -81,6 +101,7 @@ pub fn get_old_spheres() -> SphereList {
                 radius: 1.5,
                 material: Material::Dielectric(Dielectric {
                     reflective_index: 1.5,
+                    dispersion: Some(Dispersion::dense_flint()),
                 }),
             }),
             SphereThing::S(Sphere {
@@ -92,6 +113,7 @@ pub fn get_old_spheres() -> SphereList {
                 radius: -1.45,
                 material: Material::Dielectric(Dielectric {
                     reflective_index: 1.5,
+                    dispersion: Some(Dispersion::dense_flint()),
                 }),
             }),
         ],
@@ -122,6 +144,7 @@ pub fn get_spheres_many() -> SphereList {
         radius: 0.7,
         material: Material::Dielectric(Dielectric {
             reflective_index: 1.5,
+            dispersion: None,
         }),
     }));
     v.push(SphereThing::S(Sphere {
@@ -133,6 +156,7 @@ pub fn get_spheres_many() -> SphereList {
         radius: 1.0,
         material: Material::Dielectric(Dielectric {
             reflective_index: 1.5,
+            dispersion: None,
         }),
     }));
     v.push(SphereThing::S(Sphere {
@@ -148,6 +172,7 @@ pub fn get_spheres_many() -> SphereList {
                 g: 0.6,
                 b: 0.5,
             },
+            fuzz: 0.1,
         }),
     }));
 
@@ -174,9 +199,11 @@ pub fn get_spheres_many() -> SphereList {
                         g: rnd(),
                         b: rnd(),
                     },
+                    fuzz: 0.1,
                 }),
                 _ => Material::Dielectric(Dielectric {
                     reflective_index: 1.5,
+                    dispersion: None,
                 }),
             };
 
@@ -205,4 +232,236 @@ pub fn get_spheres_many() -> SphereList {
     }
 
     SphereList { spheres: v }
-}
\ No newline at end of file
+}
+
+#[allow(dead_code)]
+pub fn get_transformed_spheres() -> SphereList {
+    let mut spheres = get_old_spheres().spheres;
+    let globe = spheres.remove(0);
+    let up = Point {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+
+    // The same globe squashed, tilted and placed three times
+    for i in 0..3 {
+        let affine: Affine = NO_TRANSFORM
+            .scale(Point {
+                x: 1.0,
+                y: 0.6,
+                z: 1.0,
+            }).rotate(
+                Point {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 1.0,
+                },
+                23.5,
+            ).rotate(up, i as f32 * 120.0)
+            .translate(Point {
+                x: -2.0 + i as f32 * 2.0,
+                y: 0.0,
+                z: -2.5,
+            });
+        spheres.push(SphereThing::TR(Transform::new(globe.clone(), affine)));
+    }
+    SphereList { spheres }
+}
+
+#[allow(dead_code)]
+pub fn get_instanced_spheres() -> SphereList {
+    // One ring of spheres built into a single bottom level BVH
+    let mut ring = vec![];
+    for i in 0..12 {
+        let angle = i as f32 * 30.0_f32.to_radians();
+        let material = match i % 3 {
+            0 => Material::Conductor(Conductor::gold(0.3)),
+            1 => Material::Dielectric(Dielectric {
+                reflective_index: 1.5,
+                dispersion: None,
+            }),
+            _ => Material::Lambertian(Lambertian {
+                texture: Texture::T(ConstantTexture {
+                    color: Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.5,
+                    },
+                }),
+            }),
+        };
+        ring.push(SphereThing::S(Sphere {
+            center: Point {
+                x: angle.cos(),
+                y: 0.0,
+                z: angle.sin(),
+            },
+            radius: 0.25,
+            material,
+        }));
+    }
+    let blas = Arc::new(spheres_to_bounding_box(ring));
+
+    let mut v = vec![SphereThing::S(Sphere {
+        center: Point {
+            x: 0.0,
+            y: -1000.0,
+            z: 0.0,
+        },
+        radius: 1000.0,
+        material: Material::Lambertian(Lambertian {
+            texture: Texture::NT(build_noise()),
+        }),
+    })];
+    for a in -3..3 {
+        for b in -3..3 {
+            let affine = NO_TRANSFORM
+                .rotate(
+                    Point {
+                        x: 1.0,
+                        y: 0.0,
+                        z: 0.0,
+                    },
+                    rnd() * 90.0,
+                ).translate(Point {
+                    x: a as f32 * 2.5,
+                    y: 1.3,
+                    z: b as f32 * 2.5,
+                });
+            v.push(SphereThing::I(Instance::new(&blas, affine)));
+        }
+    }
+    SphereList { spheres: v }
+}
+
+#[allow(dead_code)]
+pub fn get_animated_spheres() -> SphereList {
+    let mut spheres = get_old_spheres().spheres;
+    // Move the globe to the origin so the keyframes spin it in place
+    let (globe, center) = match spheres.remove(0) {
+        SphereThing::S(s) => (
+            SphereThing::S(Sphere {
+                center: Point {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                ..s
+            }),
+            s.center,
+        ),
+        _ => panic!("Expected the globe to be a plain sphere"),
+    };
+    let unit = Point {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    };
+    // Spins a third of a turn and drifts left over the shutter interval
+    let motion = AnimatedTransform::new(vec![
+        Keyframe {
+            time: 0.0,
+            translation: center,
+            rotation: NO_ROTATION,
+            scale: unit,
+        },
+        Keyframe {
+            time: 1.0,
+            translation: center + Point {
+                x: 0.0,
+                y: 0.0,
+                z: -0.4,
+            },
+            rotation: Quaternion::from_axis_angle(
+                Point {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 0.0,
+                },
+                120.0,
+            ),
+            scale: unit,
+        },
+    ]);
+    spheres.push(SphereThing::A(Animated::new(globe, motion)));
+    SphereList { spheres }
+}
+
+/// The ground from get_old_spheres with a row of spheres across the view
+/// to compare materials side by side
+#[allow(dead_code)]
+pub fn get_material_spheres() -> SphereList {
+    let ground = get_old_spheres().spheres.remove(1);
+    let materials = vec![
+        Material::RoughDielectric(RoughDielectric {
+            reflective_index: 1.5,
+            roughness: 0.3,
+        }),
+        // Red plastic under a clear lacquer
+        Material::Principled(Principled {
+            base_color: Texture::T(ConstantTexture {
+                color: Color {
+                    r: 0.7,
+                    g: 0.1,
+                    b: 0.1,
+                },
+            }),
+            roughness: Param::Value(0.4),
+            clearcoat: Param::Value(1.0),
+            ..Principled::default()
+        }),
+        // Brushed copper under a slightly amber varnish
+        Material::Coated(Coated {
+            base: Box::new(Material::Conductor(Conductor::copper(0.4))),
+            reflective_index: 1.5,
+            roughness: 0.05,
+            thickness: 0.2,
+            absorption: Color {
+                r: 0.0,
+                g: 0.5,
+                b: 1.5,
+            },
+        }),
+        // Gold showing through blue paint wherever the noise is bright
+        Material::Mix(Mix {
+            a: Box::new(Material::Lambertian(Lambertian {
+                texture: Texture::T(ConstantTexture {
+                    color: Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.5,
+                    },
+                }),
+            })),
+            b: Box::new(Material::Conductor(Conductor::gold(0.2))),
+            weight: Param::Map(Box::new(Texture::NT(build_noise()))),
+        }),
+        // Clay, flatter looking than Lambertian towards the edges
+        Material::OrenNayar(OrenNayar {
+            texture: Texture::T(ConstantTexture {
+                color: Color {
+                    r: 0.7,
+                    g: 0.45,
+                    b: 0.3,
+                },
+            }),
+            roughness: 1.0,
+        }),
+    ];
+    // The camera looks along -x so the row runs along z
+    let start = (materials.len() - 1) as f32 * 0.45;
+    let mut spheres = vec![ground];
+    for (i, material) in materials.into_iter().enumerate() {
+        spheres.push(SphereThing::S(Sphere {
+            center: Point {
+                x: 0.0,
+                y: -0.1,
+                z: start - i as f32 * 0.9,
+            },
+            radius: 0.4,
+            material,
+        }));
+    }
+    SphereList { spheres }
+}
@@ -1,5 +1,20 @@
+use fresnel_conductor_rgb;
+use fresnel_dielectric;
+use luminance;
+use reflect_about;
+use refract_through;
 use rnd;
+use roughness_to_alpha;
+use sample_vndf;
+use sample_wavelength;
+use smith_g1;
+use smith_g2;
+use std::f32::consts::PI;
+use wavelength_to_rgb;
 use Color;
+use ConstantTexture;
+use Frame;
+use Hit;
 use Point;
 use Ray;
 use Texture;
@@ -18,6 +33,18 @@ fn random_in_sphere() -> Point {
     }
 }
 
+/// Local direction around +z with density cos(theta) / pi
+fn random_cosine_direction() -> Point {
+    let u1 = rnd();
+    let phi = 2.0 * PI * rnd();
+    let r = u1.sqrt();
+    Point {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z: (1.0 - u1).max(0.0).sqrt(),
+    }
+}
+
 fn reflect(v: Point, n: &Point) -> Point {
     v - (*n * (2.0 * v.dot(&n)))
 }
@@ -43,6 +70,8 @@ fn schlick(cos: f32, ref_idx: f32) -> f32 {
 #[derive(Clone)]
 pub struct Metal {
     pub albedo: Color,
+    /// Radius of the random nudge given to reflections, 0 is a mirror
+    pub fuzz: f32,
 }
 #[derive(Clone)]
 pub struct Lambertian {
@@ -51,6 +80,569 @@ pub struct Lambertian {
 #[derive(Clone)]
 pub struct Dielectric {
     pub reflective_index: f32,
+    /// Index that changes with wavelength, used instead of
+    /// `reflective_index` when set
+    pub dispersion: Option<Dispersion>,
+}
+
+/// Refractive index as a function of wavelength
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    /// n = a + b / lambda^2 with lambda in micrometres
+    Cauchy { a: f32, b: f32 },
+    /// The usual three term Sellmeier equation, c in square micrometres
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7, ordinary crown glass
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        }
+    }
+
+    /// Dense flint, about five times the spread of BK7
+    pub fn dense_flint() -> Dispersion {
+        Dispersion::Cauchy {
+            a: 1.7280,
+            b: 0.013_42,
+        }
+    }
+
+    pub fn ior(&self, nanometres: f32) -> f32 {
+        let l2 = (nanometres / 1000.0) * (nanometres / 1000.0);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+impl Dielectric {
+    /// With dispersion an RGB path that doesn't have a wavelength yet picks
+    /// a single one here and is weighted by its colour, so the spectrum is
+    /// only built up over many paths. From then on every other dispersive
+    /// surface bends it by that wavelength. Spectral paths arrive with the
+    /// hero wavelength of their samples already set.
+    fn scatter(&self, r: &Ray, hit: &Hit) -> Option<Scatter> {
+        let (ior, wavelength, attenuation) = match self.dispersion {
+            Some(dispersion) if r.wavelength > 0.0 => {
+                (dispersion.ior(r.wavelength), r.wavelength, hit.color)
+            }
+            Some(dispersion) => {
+                let lambda = sample_wavelength(rnd());
+                (
+                    dispersion.ior(lambda),
+                    lambda,
+                    hit.color.mul(&wavelength_to_rgb(lambda)),
+                )
+            }
+            None => (self.reflective_index, r.wavelength, hit.color),
+        };
+        self.scatter_ray(r, hit.normal, hit.point, ior, wavelength)
+            .map(|ray| Scatter { ray, attenuation })
+    }
+
+    fn scatter_ray(
+        &self,
+        r: &Ray,
+        normal: Point,
+        p: Point,
+        ior: f32,
+        wavelength: f32,
+    ) -> Option<Ray> {
+        let (outward_normal, ni_over_nt, cos) = {
+            if r.direction.dot(&normal) > 0.0 {
+                let cos = ior * r.direction.dot(&normal) / r.direction.length();
+                (normal * -1.0, ior, cos)
+            } else {
+                let cos = -r.direction.dot(&normal) / r.direction.length();
+                (normal, 1.0 / ior, cos)
+            }
+        };
+        match refract(&r.direction, outward_normal, ni_over_nt) {
+            Some(refracted) => {
+                let reflect_prob = schlick(cos, ior);
+                if reflect_prob < rnd() {
+                    let reflected = reflect(r.direction, &normal);
+                    Some(Ray {
+                        origin: p,
+                        direction: reflected,
+                        time: r.time,
+                        wavelength,
+                    })
+                } else {
+                    Some(Ray {
+                        origin: p,
+                        direction: refracted,
+                        time: r.time,
+                        wavelength,
+                    })
+                }
+            }
+            None => {
+                let reflected = reflect(r.direction, &normal);
+                Some(Ray {
+                    origin: p,
+                    direction: reflected,
+                    time: r.time,
+                    wavelength,
+                })
+            }
+        }
+    }
+}
+
+/// Metal as a GGX microfacet surface with a complex index of refraction per
+/// colour channel, which gives the tinted, angle dependent reflections real
+/// metals have.
+#[derive(Clone)]
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    /// 0 is a mirror, 1 is very brushed
+    pub roughness: f32,
+}
+
+impl Conductor {
+    pub fn gold(roughness: f32) -> Conductor {
+        Conductor {
+            eta: Color {
+                r: 0.143,
+                g: 0.374,
+                b: 1.442,
+            },
+            k: Color {
+                r: 3.983,
+                g: 2.385,
+                b: 1.603,
+            },
+            roughness,
+        }
+    }
+
+    pub fn copper(roughness: f32) -> Conductor {
+        Conductor {
+            eta: Color {
+                r: 0.200,
+                g: 0.924,
+                b: 1.102,
+            },
+            k: Color {
+                r: 3.912,
+                g: 2.452,
+                b: 2.142,
+            },
+            roughness,
+        }
+    }
+
+    pub fn aluminum(roughness: f32) -> Conductor {
+        Conductor {
+            eta: Color {
+                r: 1.657,
+                g: 0.880,
+                b: 0.521,
+            },
+            k: Color {
+                r: 9.224,
+                g: 6.270,
+                b: 4.837,
+            },
+            roughness,
+        }
+    }
+
+    fn scatter(&self, r: &Ray, hit: &Hit) -> Option<Scatter> {
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(&(r.direction.unit_vector() * -1.0));
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let alpha = roughness_to_alpha(self.roughness);
+        let m = sample_vndf(&wo, alpha, rnd(), rnd());
+        let wi = reflect_about(&wo, &m);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        // Sampling visible normals leaves only Fresnel and the shadowing term
+        let fresnel = fresnel_conductor_rgb(wo.dot(&m), &self.eta, &self.k);
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.point,
+                direction: frame.to_world(&wi),
+                time: r.time,
+                wavelength: r.wavelength,
+            },
+            attenuation: fresnel * (smith_g2(&wo, &wi, alpha) / smith_g1(&wo, alpha)),
+        })
+    }
+}
+
+/// Frosted glass: a dielectric whose surface is GGX microfacets, so both
+/// the reflection and the refraction are blurred by `roughness`
+#[derive(Clone)]
+pub struct RoughDielectric {
+    pub reflective_index: f32,
+    pub roughness: f32,
+}
+
+impl RoughDielectric {
+    fn scatter(&self, r: &Ray, hit: &Hit) -> Option<Scatter> {
+        let incoming = r.direction.unit_vector() * -1.0;
+        // Work on the side the ray came from, eta is inside over outside
+        let (normal, eta) = if incoming.dot(&hit.normal) > 0.0 {
+            (hit.normal, self.reflective_index)
+        } else {
+            (hit.normal * -1.0, 1.0 / self.reflective_index)
+        };
+        let frame = Frame::new(normal);
+        let wo = frame.to_local(&incoming);
+        let alpha = roughness_to_alpha(self.roughness);
+        let m = sample_vndf(&wo, alpha, rnd(), rnd());
+
+        // Choosing by Fresnel cancels it out of the weight
+        let refracted = if rnd() < fresnel_dielectric(wo.dot(&m), eta) {
+            None
+        } else {
+            refract_through(&wo, &m, eta)
+        };
+        let wi = match refracted {
+            Some(wi) if wi.z < 0.0 => wi,
+            Some(_) => return None,
+            None => {
+                let wi = reflect_about(&wo, &m);
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                wi
+            }
+        };
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.point,
+                direction: frame.to_world(&wi),
+                time: r.time,
+                wavelength: r.wavelength,
+            },
+            attenuation: PURE_COLOR * (smith_g2(&wo, &wi, alpha) / smith_g1(&wo, alpha)),
+        })
+    }
+}
+
+/// A material input that is either one number or read from the red channel
+/// of a texture, the way glTF packs its metallic and roughness maps
+#[derive(Clone)]
+pub enum Param {
+    Value(f32),
+    Map(Box<Texture>),
+}
+
+impl Param {
+    pub fn at(&self, p: &Point, u: f32, v: f32) -> f32 {
+        match self {
+            Param::Value(x) => *x,
+            Param::Map(t) => t.value(p, u, v).r,
+        }
+    }
+}
+
+const CLEARCOAT_ROUGHNESS: f32 = 0.1;
+
+/// Disney style uber material (Burley 2012). Each call picks one lobe: the
+/// clearcoat by its Fresnel, then metal by `metallic`, glass by
+/// `transmission`, and otherwise a plastic of GGX specular over a diffuse
+/// base with sheen at grazing angles.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Texture,
+    pub metallic: Param,
+    pub roughness: Param,
+    /// Scales the plastic's head on reflectance, 0.5 is 4%
+    pub specular: Param,
+    /// How much that reflection takes on the base colour
+    pub specular_tint: Param,
+    pub sheen: Param,
+    pub clearcoat: Param,
+    pub transmission: Param,
+    pub reflective_index: Param,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_color: Texture::T(ConstantTexture {
+                color: Color {
+                    r: 0.8,
+                    g: 0.8,
+                    b: 0.8,
+                },
+            }),
+            metallic: Param::Value(0.0),
+            roughness: Param::Value(0.5),
+            specular: Param::Value(0.5),
+            specular_tint: Param::Value(0.0),
+            sheen: Param::Value(0.0),
+            clearcoat: Param::Value(0.0),
+            transmission: Param::Value(0.0),
+            reflective_index: Param::Value(1.5),
+        }
+    }
+}
+
+fn schlick_color(f0: &Color, cos: f32) -> Color {
+    let edge = (1.0 - cos).max(0.0).powi(5);
+    *f0 + (PURE_COLOR - *f0) * edge
+}
+
+fn lerp(a: &Color, b: &Color, t: f32) -> Color {
+    *a * (1.0 - t) + *b * t
+}
+
+/// GGX reflection off microfacets with Fresnel `fresnel(wo . m)`
+fn glossy(
+    r: &Ray,
+    hit: &Hit,
+    frame: &Frame,
+    wo: &Point,
+    roughness: f32,
+    fresnel: &dyn Fn(f32) -> Color,
+) -> Option<Scatter> {
+    let alpha = roughness_to_alpha(roughness);
+    let m = sample_vndf(wo, alpha, rnd(), rnd());
+    let wi = reflect_about(wo, &m);
+    if wi.z <= 0.0 {
+        return None;
+    }
+    Some(Scatter {
+        ray: Ray {
+            origin: hit.point,
+            direction: frame.to_world(&wi),
+            time: r.time,
+            wavelength: r.wavelength,
+        },
+        attenuation: fresnel(wo.dot(&m)) * (smith_g2(wo, &wi, alpha) / smith_g1(wo, alpha)),
+    })
+}
+
+impl Principled {
+    fn scatter(&self, r: &Ray, hit: &Hit) -> Option<Scatter> {
+        let at = |param: &Param| param.at(&hit.point, hit.u, hit.v);
+        let base = hit.color;
+        let roughness = at(&self.roughness);
+        let transmission = at(&self.transmission);
+        let glass = || {
+            let glass = RoughDielectric {
+                reflective_index: at(&self.reflective_index),
+                roughness,
+            };
+            // Tinted at both surfaces so a round trip comes out as the base
+            let tint = Color {
+                r: base.r.sqrt(),
+                g: base.g.sqrt(),
+                b: base.b.sqrt(),
+            };
+            glass.scatter(r, hit).map(|s| Scatter {
+                attenuation: s.attenuation.mul(&tint),
+                ..s
+            })
+        };
+
+        let incoming = r.direction.unit_vector() * -1.0;
+        if incoming.dot(&hit.normal) <= 0.0 {
+            // Only the glass lobe lets rays inside
+            return glass();
+        }
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(&incoming);
+
+        if rnd() < at(&self.clearcoat) * schlick(wo.z, 1.5) {
+            return glossy(r, hit, &frame, &wo, CLEARCOAT_ROUGHNESS, &|_| PURE_COLOR);
+        }
+        if rnd() < at(&self.metallic) {
+            return glossy(r, hit, &frame, &wo, roughness, &|cos| {
+                schlick_color(&base, cos)
+            });
+        }
+        if rnd() < transmission {
+            return glass();
+        }
+
+        let tint = if luminance(&base) > 0.0 {
+            base / luminance(&base)
+        } else {
+            PURE_COLOR
+        };
+        let f0 = lerp(&PURE_COLOR, &tint, at(&self.specular_tint)) * (0.08 * at(&self.specular));
+        // The lobe is picked by Fresnel about the shading normal, the
+        // specular weight then uses the microfacet glossy actually samples
+        let fresnel = schlick_color(&f0, wo.z);
+        let specular_prob = (fresnel.r + fresnel.g + fresnel.b) / 3.0;
+        if rnd() < specular_prob {
+            return glossy(r, hit, &frame, &wo, roughness, &|cos| {
+                schlick_color(&f0, cos) / specular_prob
+            });
+        }
+
+        let direction = hit.normal + random_in_sphere();
+        let wi = frame.to_local(&direction.unit_vector());
+        let half = (wi + wo).unit_vector();
+        // Sheen brightens the base towards white at grazing angles rather
+        // than adding to it, so the diffuse lobe never gives back more light
+        // than it received
+        let sheen = (at(&self.sheen) * (1.0 - wi.dot(&half)).max(0.0).powi(5)).min(1.0);
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.point,
+                direction,
+                time: r.time,
+                wavelength: r.wavelength,
+            },
+            attenuation: lerp(&base, &PURE_COLOR, sheen).mul(&(PURE_COLOR - fresnel))
+                / (1.0 - specular_prob),
+        })
+    }
+}
+
+/// A clear varnish over another material: car paint, lacquered wood,
+/// glossy plastic. Light either bounces off the coat, chosen by its Fresnel
+/// term, or passes through it twice around a bounce off `base`, losing some
+/// to absorption on the way and to Fresnel again on the way out.
+#[derive(Clone)]
+pub struct Coated {
+    pub base: Box<Material>,
+    pub reflective_index: f32,
+    pub roughness: f32,
+    pub thickness: f32,
+    /// Per unit of thickness, 0 is perfectly clear
+    pub absorption: Color,
+}
+
+impl Coated {
+    /// Cosine of a ray inside the coat that is at `cos` outside it
+    fn cos_inside(&self, cos: f32) -> f32 {
+        let sin2 = (1.0 - cos * cos) / (self.reflective_index * self.reflective_index);
+        (1.0 - sin2).max(0.0).sqrt()
+    }
+
+    fn scatter(&self, r: &Ray, hit: &Hit) -> Option<Scatter> {
+        let incoming = r.direction.unit_vector() * -1.0;
+        let cos_in = incoming.dot(&hit.normal);
+        if cos_in <= 0.0 {
+            return self.base.scatter(r, hit);
+        }
+        if rnd() < fresnel_dielectric(cos_in, self.reflective_index) {
+            let frame = Frame::new(hit.normal);
+            let wo = frame.to_local(&incoming);
+            return glossy(r, hit, &frame, &wo, self.roughness, &|_| PURE_COLOR);
+        }
+
+        let bounce = self.base.scatter(r, hit)?;
+        let cos_out = bounce.ray.direction.unit_vector().dot(&hit.normal);
+        if cos_out <= 0.0 {
+            // The base let it through, the coat doesn't change that
+            return Some(bounce);
+        }
+        let path =
+            self.thickness * (1.0 / self.cos_inside(cos_in) + 1.0 / self.cos_inside(cos_out));
+        let transmittance = Color {
+            r: (-self.absorption.r * path).exp(),
+            g: (-self.absorption.g * path).exp(),
+            b: (-self.absorption.b * path).exp(),
+        };
+        let leaving = 1.0 - fresnel_dielectric(cos_out, self.reflective_index);
+        Some(Scatter {
+            attenuation: bounce.attenuation.mul(&transmittance) * leaving,
+            ..bounce
+        })
+    }
+}
+
+/// Picks `a` or `b` at random for each hit, `b` with probability `weight`.
+/// With a texture as the weight this paints one material over another, like
+/// rust patches on metal.
+#[derive(Clone)]
+pub struct Mix {
+    pub a: Box<Material>,
+    pub b: Box<Material>,
+    pub weight: Param,
+}
+
+impl Mix {
+    fn weight(&self, p: &Point, u: f32, v: f32) -> f32 {
+        self.weight.at(p, u, v).clamp(0.0, 1.0)
+    }
+
+    fn scatter(&self, r: &Ray, hit: &Hit) -> Option<Scatter> {
+        let chosen: &Material = if rnd() < self.weight(&hit.point, hit.u, hit.v) {
+            &self.b
+        } else {
+            &self.a
+        };
+        // The hit's colour is the blend, the chosen material wants its own
+        let hit = Hit {
+            color: chosen.get_albedo(&hit.point, hit.u, hit.v),
+            material: chosen,
+            ..*hit
+        };
+        chosen.scatter(r, &hit)
+    }
+}
+
+/// Rough diffuse surface made of tiny Lambertian V grooves (Oren and Nayar
+/// 1994, qualitative model). Flatter and brighter towards the light than
+/// Lambertian, the look of clay, concrete and the moon.
+#[derive(Clone)]
+pub struct OrenNayar {
+    pub texture: Texture,
+    /// Standard deviation of the groove angles in radians, 0 is Lambertian
+    pub roughness: f32,
+}
+
+impl OrenNayar {
+    fn scatter(&self, r: &Ray, hit: &Hit) -> Option<Scatter> {
+        let incoming = r.direction.unit_vector() * -1.0;
+        let normal = if incoming.dot(&hit.normal) >= 0.0 {
+            hit.normal
+        } else {
+            hit.normal * -1.0
+        };
+        let frame = Frame::new(normal);
+        let wo = frame.to_local(&incoming);
+        let wi = random_cosine_direction();
+
+        let sigma2 = self.roughness * self.roughness;
+        let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+        let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x * wo.x + wi.y * wo.y) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+        // sin(alpha) * tan(beta) with alpha the larger of the two angles
+        let sin_tan = if wi.z.abs() > wo.z.abs() {
+            sin_o * sin_i / wi.z.abs().max(1e-4)
+        } else {
+            sin_i * sin_o / wo.z.abs().max(1e-4)
+        };
+        // Cosine sampling cancels the cosine and the 1 / pi
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.point,
+                direction: frame.to_world(&wi),
+                time: r.time,
+                wavelength: r.wavelength,
+            },
+            attenuation: hit.color * (a + b * cos_phi * sin_tan),
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -58,19 +650,50 @@ pub enum Material {
     Metal(Metal),
     Lambertian(Lambertian),
     Dielectric(Dielectric),
+    Conductor(Conductor),
+    RoughDielectric(RoughDielectric),
+    Principled(Principled),
+    Coated(Coated),
+    Mix(Mix),
+    OrenNayar(OrenNayar),
+}
+
+/// Where the light came from and how much of it survives the bounce
+pub struct Scatter {
+    pub ray: Ray,
+    pub attenuation: Color,
 }
 
 impl Material {
+    pub fn scatter(&self, r: &Ray, hit: &Hit) -> Option<Scatter> {
+        let bounce = |ray: Option<Ray>| {
+            ray.map(|ray| Scatter {
+                ray,
+                attenuation: hit.color,
+            })
+        };
+        match self {
+            Material::Dielectric(d) => d.scatter(r, hit),
+            Material::Conductor(c) => c.scatter(r, hit),
+            Material::RoughDielectric(d) => d.scatter(r, hit),
+            Material::Principled(p) => p.scatter(r, hit),
+            Material::Coated(c) => c.scatter(r, hit),
+            Material::Mix(m) => m.scatter(r, hit),
+            Material::OrenNayar(o) => o.scatter(r, hit),
+            _ => bounce(self.scatter_ray(r, hit.normal, hit.point)),
+        }
+    }
+
     // pop this out without the match move to each subclass
-    pub fn scatter(&self, r: &Ray, normal: Point, p: Point) -> Option<Ray> {
+    fn scatter_ray(&self, r: &Ray, normal: Point, p: Point) -> Option<Ray> {
         match self {
-            Material::Metal(_metal) => {
+            Material::Metal(metal) => {
                 let reflected = reflect(r.direction.unit_vector(), &normal);
-                // hard code fuzz of 0.1
                 let scattered = Ray {
                     origin: p,
-                    direction: reflected + random_in_sphere() * 0.1,
+                    direction: reflected + random_in_sphere() * metal.fuzz,
                     time: r.time,
+                    wavelength: r.wavelength,
                 };
                 if scattered.direction.dot(&normal) > 0.0 {
                     Some(scattered)
@@ -84,47 +707,36 @@ impl Material {
                     origin: p,
                     direction: target,
                     time: r.time,
+                    wavelength: r.wavelength,
                 })
             }
-            Material::Dielectric(d) => {
-                let (outward_normal, ni_over_nt, cos) = {
-                    if r.direction.dot(&normal) > 0.0 {
-                        let cos =
-                            d.reflective_index * r.direction.dot(&normal) / r.direction.length();
-                        (normal * -1.0, d.reflective_index, cos)
-                    } else {
-                        let cos = -r.direction.dot(&normal) / r.direction.length();
-                        (normal, 1.0 / d.reflective_index, cos)
-                    }
-                };
-                match refract(&r.direction, outward_normal, ni_over_nt) {
-                    Some(refracted) => {
-                        let reflect_prob = schlick(cos, d.reflective_index);
-                        if reflect_prob < rnd() {
-                            let reflected = reflect(r.direction, &normal);
-                            Some(Ray {
-                                origin: p,
-                                direction: reflected,
-                                time: r.time,
-                            })
-                        } else {
-                            Some(Ray {
-                                origin: p,
-                                direction: refracted,
-                                time: r.time,
-                            })
-                        }
-                    }
-                    None => {
-                        let reflected = reflect(r.direction, &normal);
-                        Some(Ray {
-                            origin: p,
-                            direction: reflected,
-                            time: r.time,
-                        })
-                    }
-                }
-            }
+            _ => None,
+        }
+    }
+
+    /// Whether scattering can depend on the ray's wavelength. Errs on the
+    /// side of yes for materials that only sometimes are.
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Material::Dielectric(d) => d.dispersion.is_some(),
+            Material::Coated(c) => c.base.is_dispersive(),
+            Material::Mix(m) => m.a.is_dispersive() || m.b.is_dispersive(),
+            _ => false,
+        }
+    }
+
+    /// Which kind of material this is, for the material ID pass
+    pub fn id(&self) -> usize {
+        match self {
+            Material::Metal(_) => 0,
+            Material::Lambertian(_) => 1,
+            Material::Dielectric(_) => 2,
+            Material::Conductor(_) => 3,
+            Material::RoughDielectric(_) => 4,
+            Material::Principled(_) => 5,
+            Material::Coated(_) => 6,
+            Material::Mix(_) => 7,
+            Material::OrenNayar(_) => 8,
         }
     }
 
@@ -132,7 +744,217 @@ impl Material {
         match self {
             Material::Metal(metal) => metal.albedo,
             Material::Lambertian(l) => l.texture.value(p, u, v),
-            Material::Dielectric(_) => PURE_COLOR,
+            Material::Dielectric(_) | Material::RoughDielectric(_) => PURE_COLOR,
+            Material::Conductor(c) => fresnel_conductor_rgb(1.0, &c.eta, &c.k),
+            Material::Principled(pr) => pr.base_color.value(p, u, v),
+            Material::Coated(c) => c.base.get_albedo(p, u, v),
+            Material::OrenNayar(o) => o.texture.value(p, u, v),
+            Material::Mix(m) => {
+                let w = m.weight(p, u, v);
+                m.a.get_albedo(p, u, v) * (1.0 - w) + m.b.get_albedo(p, u, v) * w
+            }
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use NO_COLOR;
+
+    /// Light arriving at about 27 degrees from the normal (straight up)
+    #[allow(dead_code)]
+    fn incoming() -> Ray {
+        Ray {
+            origin: Point {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            direction: Point {
+                x: 0.5,
+                y: -1.0,
+                z: 0.0,
+            },
+            time: 0.0,
+            wavelength: 0.0,
+        }
+    }
+
+    /// Scatters `incoming` off a flat patch of `material` n times
+    #[allow(dead_code)]
+    fn scatter_samples(material: &Material, n: usize) -> Vec<Option<Scatter>> {
+        let up = Point {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        let hit = Hit {
+            color: material.get_albedo(&up, 0.0, 0.0),
+            point: up * 0.0,
+            normal: up,
+            u: 0.0,
+            v: 0.0,
+            material,
+            object_id: 0,
+            t: 1.0,
+        };
+        (0..n)
+            .map(|_| material.scatter(&incoming(), &hit))
+            .collect()
+    }
+
+    /// Mean attenuation of light reflected off `material`
+    #[allow(dead_code)]
+    fn average_color(material: &Material) -> Color {
+        let n = 20000;
+        let mut total = NO_COLOR;
+        for s in scatter_samples(material, n).iter().flatten() {
+            assert!(s.ray.direction.y > 0.0);
+            total += s.attenuation;
+        }
+        total / n as f32
+    }
+
+    #[allow(dead_code)]
+    fn average_reflectance(material: &Material) -> f32 {
+        average_color(material).g
+    }
+
+    #[allow(dead_code)]
+    fn lambertian(c: f32) -> Material {
+        Material::Lambertian(Lambertian {
+            texture: Texture::T(ConstantTexture {
+                color: Color { r: c, g: c, b: c },
+            }),
+        })
+    }
+
+    #[test]
+    fn test_principled_does_not_add_energy() {
+        let white = Material::Principled(Principled {
+            base_color: Texture::T(ConstantTexture { color: PURE_COLOR }),
+            sheen: Param::Value(1.0),
+            clearcoat: Param::Value(1.0),
+            ..Principled::default()
+        });
+        let average = average_reflectance(&white);
+        // Allowing a little for noise
+        assert!(average > 0.8 && average < 1.01, "reflected {}", average);
+    }
+
+    #[test]
+    fn test_coat_reflects_its_fresnel() {
+        let coated = |base: Material| {
+            Material::Coated(Coated {
+                base: Box::new(base),
+                reflective_index: 1.5,
+                roughness: 0.0,
+                thickness: 0.1,
+                absorption: NO_COLOR,
+            })
+        };
+        // Over black only the coat's own few percent comes back
+        let black = average_reflectance(&coated(lambertian(0.0)));
+        assert!(black > 0.03 && black < 0.08, "reflected {}", black);
+        let white = average_reflectance(&coated(lambertian(1.0)));
+        assert!(white > 0.8 && white <= 1.0, "reflected {}", white);
+    }
+
+    #[test]
+    fn test_oren_nayar_roughness() {
+        let oren_nayar = |roughness: f32| {
+            Material::OrenNayar(OrenNayar {
+                texture: Texture::T(ConstantTexture { color: PURE_COLOR }),
+                roughness,
+            })
+        };
+        // Smooth is exactly Lambertian, rough loses a little to the grooves
+        assert!((average_reflectance(&oren_nayar(0.0)) - 1.0).abs() < 1e-4);
+        let rough = average_reflectance(&oren_nayar(1.0));
+        assert!(rough > 0.5 && rough < 1.0, "reflected {}", rough);
+    }
+
+    #[test]
+    fn test_dispersion_bends_blue_more() {
+        let bk7 = Dispersion::bk7();
+        assert!((bk7.ior(587.6) - 1.5168).abs() < 1e-3);
+        for glass in &[bk7, Dispersion::dense_flint()] {
+            assert!(glass.ior(450.0) > glass.ior(650.0));
+        }
+    }
+
+    #[test]
+    fn test_mix_by_weight() {
+        let mix = |weight: Param| {
+            Material::Mix(Mix {
+                a: Box::new(lambertian(1.0)),
+                b: Box::new(lambertian(0.0)),
+                weight,
+            })
+        };
+        let quarter = average_reflectance(&mix(Param::Value(0.25)));
+        assert!((quarter - 0.75).abs() < 0.03, "reflected {}", quarter);
+        let mask = Param::Map(Box::new(Texture::T(ConstantTexture { color: PURE_COLOR })));
+        assert_eq!(average_reflectance(&mix(mask)), 0.0);
+    }
+
+    #[test]
+    fn test_conductor_colour_and_spread() {
+        let gold = average_color(&Material::Conductor(Conductor::gold(0.2)));
+        assert!(gold.r > gold.b, "reflected {:?}", gold);
+        assert!(gold.r <= 1.0 && gold.g <= 1.0);
+
+        // Average angle away from the mirror direction
+        let spread = |roughness: f32| {
+            let mirror = Point {
+                x: 0.5,
+                y: 1.0,
+                z: 0.0,
+            }
+            .unit_vector();
+            let samples =
+                scatter_samples(&Material::Conductor(Conductor::aluminum(roughness)), 2000);
+            let directions: Vec<Point> = samples
+                .iter()
+                .flatten()
+                .map(|s| s.ray.direction.unit_vector())
+                .collect();
+            directions
+                .iter()
+                .map(|d| d.dot(&mirror).min(1.0).acos())
+                .sum::<f32>()
+                / directions.len() as f32
+        };
+        let smooth = spread(0.05);
+        assert!(smooth < 0.05, "spread {}", smooth);
+        assert!(spread(0.6) > smooth * 4.0);
+    }
+
+    #[test]
+    fn test_rough_dielectric_splits_energy() {
+        let glass = Material::RoughDielectric(RoughDielectric {
+            reflective_index: 1.5,
+            roughness: 0.3,
+        });
+        let n = 20000;
+        let (mut reflected, mut transmitted) = (0.0, 0.0);
+        for s in scatter_samples(&glass, n).iter().flatten() {
+            if s.ray.direction.y > 0.0 {
+                reflected += s.attenuation.g;
+            } else {
+                transmitted += s.attenuation.g;
+            }
         }
+        let (reflected, transmitted) = (reflected / n as f32, transmitted / n as f32);
+        // A few percent bounces off, nearly all the rest goes into the glass
+        assert!(
+            reflected > 0.02 && reflected < 0.1,
+            "reflected {}",
+            reflected
+        );
+        assert!(transmitted > 0.8, "transmitted {}", transmitted);
+        assert!(reflected + transmitted <= 1.0);
     }
 }
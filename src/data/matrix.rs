@@ -0,0 +1,203 @@
+use std::f32::consts::PI;
+
+use Point;
+
+/// Row major 4x4 matrix. Points are treated as column vectors with w = 1
+/// and directions with w = 0.
+#[derive(Debug, Clone, Copy)]
+pub struct Matrix4 {
+    pub m: [[f32; 4]; 4],
+}
+
+pub const IDENTITY: Matrix4 = Matrix4 {
+    m: [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ],
+};
+
+impl Matrix4 {
+    pub fn translation(p: Point) -> Matrix4 {
+        let mut r = IDENTITY;
+        r.m[0][3] = p.x;
+        r.m[1][3] = p.y;
+        r.m[2][3] = p.z;
+        r
+    }
+
+    pub fn scaling(p: Point) -> Matrix4 {
+        let mut r = IDENTITY;
+        r.m[0][0] = p.x;
+        r.m[1][1] = p.y;
+        r.m[2][2] = p.z;
+        r
+    }
+
+    /// Rotation of `degrees` around `axis` (right handed)
+    pub fn rotation(axis: Point, degrees: f32) -> Matrix4 {
+        let a = axis.unit_vector();
+        let theta = degrees * PI / 180.0;
+        let (s, c) = theta.sin_cos();
+        let t = 1.0 - c;
+        Matrix4 {
+            m: [
+                [
+                    t * a.x * a.x + c,
+                    t * a.x * a.y - s * a.z,
+                    t * a.x * a.z + s * a.y,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.y + s * a.z,
+                    t * a.y * a.y + c,
+                    t * a.y * a.z - s * a.x,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.z - s * a.y,
+                    t * a.y * a.z + s * a.x,
+                    t * a.z * a.z + c,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn mul(&self, rhs: &Matrix4) -> Matrix4 {
+        let mut r = Matrix4 { m: [[0.0; 4]; 4] };
+        for (i, row) in r.m.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        r
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut r = Matrix4 { m: [[0.0; 4]; 4] };
+        for (i, row) in r.m.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                *cell = self.m[j][i];
+            }
+        }
+        r
+    }
+
+    /// Gauss-Jordan elimination. Returns None for singular matrices
+    /// (eg: a scale of zero)
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = IDENTITY.m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))
+                .unwrap();
+            let size = a[pivot][col].abs();
+            if size.is_nan() || size < 1e-8 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let d = a[col][col];
+            for k in 0..4 {
+                a[col][k] /= d;
+                inv[col][k] /= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= f * a[col][k];
+                        inv[row][k] -= f * inv[col][k];
+                    }
+                }
+            }
+        }
+        if inv.iter().flatten().all(|v| v.is_finite()) {
+            Some(Matrix4 { m: inv })
+        } else {
+            None
+        }
+    }
+
+    pub fn transform_point(&self, p: &Point) -> Point {
+        let m = &self.m;
+        Point {
+            x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        }
+    }
+
+    pub fn transform_vector(&self, p: &Point) -> Point {
+        let m = &self.m;
+        Point {
+            x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z,
+            y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z,
+            z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z,
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_inverse() {
+        let m = Matrix4::translation(Point {
+            x: 1.0,
+            y: -2.0,
+            z: 3.0,
+        }).mul(&Matrix4::rotation(
+            Point {
+                x: 0.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            33.0,
+        )).mul(&Matrix4::scaling(Point {
+            x: 2.0,
+            y: 0.5,
+            z: 1.5,
+        }));
+        let identity = m.mul(&m.inverse().unwrap());
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((identity.m[i][j] - IDENTITY.m[i][j]).abs() < 1e-5);
+            }
+        }
+        assert!(Matrix4::scaling(Point {
+            x: 0.0,
+            y: 1.0,
+            z: 1.0
+        }).inverse()
+        .is_none());
+        let mut broken = IDENTITY;
+        broken.m[1][2] = f32::NAN;
+        assert!(broken.inverse().is_none());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let r = Matrix4::rotation(
+            Point {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            90.0,
+        );
+        let p = r.transform_point(&Point {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        });
+        assert!(p.x.abs() < 1e-6);
+        assert!((p.z + 1.0).abs() < 1e-6);
+    }
+}
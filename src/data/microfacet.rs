@@ -0,0 +1,245 @@
+use std::f32::consts::PI;
+
+use Color;
+use Point;
+
+/// Orthonormal basis around a normal, local z is the normal
+pub struct Frame {
+    s: Point,
+    t: Point,
+    n: Point,
+}
+
+impl Frame {
+    /// Duff et al. "Building an Orthonormal Basis, Revisited"
+    pub fn new(n: Point) -> Frame {
+        let sign = 1.0f32.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        Frame {
+            s: Point {
+                x: 1.0 + sign * n.x * n.x * a,
+                y: sign * b,
+                z: -sign * n.x,
+            },
+            t: Point {
+                x: b,
+                y: sign + n.y * n.y * a,
+                z: -n.y,
+            },
+            n,
+        }
+    }
+
+    pub fn to_local(&self, v: &Point) -> Point {
+        Point {
+            x: v.dot(&self.s),
+            y: v.dot(&self.t),
+            z: v.dot(&self.n),
+        }
+    }
+
+    pub fn to_world(&self, v: &Point) -> Point {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
+
+/// Artist friendly roughness to GGX alpha. Zero would be a perfect mirror
+/// which the sampling below can't represent, so it stops just short.
+pub fn roughness_to_alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(1e-3)
+}
+
+/// Mirror `v` about `m`, both pointing away from the surface
+pub fn reflect_about(v: &Point, m: &Point) -> Point {
+    *m * (2.0 * v.dot(m)) - *v
+}
+
+/// GGX normal distribution for a local half vector
+pub fn ggx_d(m: &Point, alpha: f32) -> f32 {
+    if m.z <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let cos2 = m.z * m.z;
+    let denom = cos2 * (a2 - 1.0) + 1.0;
+    a2 / (PI * denom * denom)
+}
+
+fn smith_lambda(v: &Point, alpha: f32) -> f32 {
+    let cos2 = v.z * v.z;
+    if cos2 == 0.0 {
+        return f32::INFINITY;
+    }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    ((1.0 + alpha * alpha * tan2).sqrt() - 1.0) / 2.0
+}
+
+/// Fraction of microfacets visible from direction `v`
+pub fn smith_g1(v: &Point, alpha: f32) -> f32 {
+    1.0 / (1.0 + smith_lambda(v, alpha))
+}
+
+/// Height correlated masking and shadowing
+pub fn smith_g2(wo: &Point, wi: &Point, alpha: f32) -> f32 {
+    1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha))
+}
+
+/// Samples a microfacet normal in proportion to how much of it `wo` sees
+/// (Heitz 2018, "Sampling the GGX Distribution of Visible Normals").
+/// `wo` is local and above the surface.
+pub fn sample_vndf(wo: &Point, alpha: f32, u1: f32, u2: f32) -> Point {
+    let vh = Point {
+        x: alpha * wo.x,
+        y: alpha * wo.y,
+        z: wo.z,
+    }
+    .unit_vector();
+    let lensq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if lensq > 0.0 {
+        Point {
+            x: -vh.y,
+            y: vh.x,
+            z: 0.0,
+        } / lensq.sqrt()
+    } else {
+        Point {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    };
+    let t2 = vh.cross(&t1);
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    Point {
+        x: alpha * nh.x,
+        y: alpha * nh.y,
+        z: nh.z.max(1e-6),
+    }
+    .unit_vector()
+}
+
+/// Unpolarised Fresnel reflectance of a metal with complex index eta + ik
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let t1 = a2b2 + cos2;
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+pub fn fresnel_conductor_rgb(cos_i: f32, eta: &Color, k: &Color) -> Color {
+    Color {
+        r: fresnel_conductor(cos_i, eta.r, k.r),
+        g: fresnel_conductor(cos_i, eta.g, k.g),
+        b: fresnel_conductor(cos_i, eta.b, k.b),
+    }
+}
+
+/// Fresnel reflectance going into a medium `eta` times denser than the one
+/// the light is in. Past the critical angle everything is reflected.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Bends `v` (pointing away from the surface) through the microfacet `m`,
+/// None for total internal reflection
+pub fn refract_through(v: &Point, m: &Point, eta: f32) -> Option<Point> {
+    let cos_i = v.dot(m);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(*m * (cos_i / eta - cos_t) - *v / eta)
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use rnd;
+
+    #[test]
+    fn test_vndf_samples_face_the_viewer() {
+        let wo = Point {
+            x: 0.6,
+            y: 0.0,
+            z: 0.8,
+        };
+        for _ in 0..1000 {
+            let m = sample_vndf(&wo, 0.5, rnd(), rnd());
+            assert!(m.z > 0.0 && wo.dot(&m) >= -1e-4);
+            assert!((m.length() - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let n = Point {
+            x: 0.0,
+            y: -1.0,
+            z: 0.0,
+        };
+        let frame = Frame::new(n);
+        let v = Point {
+            x: 0.3,
+            y: 0.2,
+            z: -0.9,
+        };
+        let back = frame.to_world(&frame.to_local(&v));
+        assert!((back - v).length() < 1e-5);
+        assert!((frame.to_local(&n).z - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_dielectric_fresnel() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+        // Leaving glass at a shallow angle is total internal reflection
+        assert_eq!(fresnel_dielectric(0.3, 1.0 / 1.5), 1.0);
+        let straight = Point {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        };
+        let through = refract_through(&straight, &straight, 1.5).unwrap();
+        assert!((through.z + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_gold_is_yellow_and_brightens_at_grazing() {
+        let eta = Color {
+            r: 0.143,
+            g: 0.374,
+            b: 1.442,
+        };
+        let k = Color {
+            r: 3.983,
+            g: 2.385,
+            b: 1.603,
+        };
+        let head_on = fresnel_conductor_rgb(1.0, &eta, &k);
+        assert!(head_on.r > head_on.b);
+        assert!(fresnel_conductor(0.05, eta.b, k.b) > head_on.b);
+    }
+}
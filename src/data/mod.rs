@@ -6,3 +6,18 @@ pub mod textures;
 pub mod vec3;
 pub mod camera;
 pub mod layouts;
+pub mod matrix;
+pub mod transform;
+pub mod quaternion;
+pub mod sequence;
+pub mod aperture;
+pub mod shutter;
+pub mod exposure;
+pub mod tonemap;
+pub mod film;
+pub mod aov;
+pub mod denoise;
+pub mod firefly;
+pub mod postprocess;
+pub mod microfacet;
+pub mod spectrum;
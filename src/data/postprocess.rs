@@ -0,0 +1,249 @@
+use Color;
+use Film;
+use NO_COLOR;
+
+/// Glow around anything brighter than `threshold`
+#[derive(Debug, Clone, Copy)]
+pub struct Bloom {
+    pub threshold: f32,
+    /// Standard deviation of the blur in pixels, must be more than 0
+    pub radius: f32,
+    /// How much of the blurred light is added back
+    pub strength: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Bloom {
+        Bloom {
+            threshold: 1.0,
+            radius: 8.0,
+            strength: 0.3,
+        }
+    }
+}
+
+/// Lens effects applied to the linear image after exposure, before tone
+/// mapping. Everything is off by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PostProcess {
+    pub bloom: Option<Bloom>,
+    /// How dark the corners get, 0..1
+    pub vignette: Option<f32>,
+    /// Red is pushed out and blue pulled in by this fraction of the distance
+    /// from the centre
+    pub chromatic_aberration: Option<f32>,
+}
+
+/// A resolved image that is easier to sample from than a film
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    fn from_film(film: &Film) -> Image {
+        Image {
+            width: film.width,
+            height: film.height,
+            pixels: film.resolved(),
+        }
+    }
+
+    fn to_film(&self) -> Film {
+        Film::from_pixels(self.width, self.height, self.pixels.clone())
+    }
+
+    fn at(&self, x: i64, y: i64) -> Color {
+        let x = x.max(0).min(self.width as i64 - 1) as usize;
+        let y = y.max(0).min(self.height as i64 - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    /// Bilinear lookup at continuous pixel coordinates, edges repeat
+    fn sample(&self, x: f32, y: f32) -> Color {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (self.at(x0, y0) * (1.0 - fx) + self.at(x0 + 1, y0) * fx) * (1.0 - fy)
+            + (self.at(x0, y0 + 1) * (1.0 - fx) + self.at(x0 + 1, y0 + 1) * fx) * fy
+    }
+
+    /// Centre of the image and the distance from it to a corner
+    fn centre(&self) -> (f32, f32, f32) {
+        let (cx, cy) = (self.width as f32 / 2.0, self.height as f32 / 2.0);
+        (cx, cy, (cx * cx + cy * cy).sqrt())
+    }
+}
+
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let reach = (sigma * 3.0).ceil().max(1.0) as i64;
+    let weights: Vec<f32> = (-reach..=reach)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / total).collect()
+}
+
+/// Separable blur, one pass along x then one along y
+fn blur(image: &Image, sigma: f32) -> Image {
+    let kernel = gaussian_kernel(sigma);
+    let reach = (kernel.len() / 2) as i64;
+    let pass = |from: &Image, dx: i64, dy: i64| {
+        let mut pixels = Vec::with_capacity(from.pixels.len());
+        for y in 0..from.height as i64 {
+            for x in 0..from.width as i64 {
+                let mut sum = NO_COLOR;
+                for (k, w) in kernel.iter().enumerate() {
+                    let o = k as i64 - reach;
+                    sum += from.at(x + o * dx, y + o * dy) * *w;
+                }
+                pixels.push(sum);
+            }
+        }
+        Image {
+            width: from.width,
+            height: from.height,
+            pixels,
+        }
+    };
+    pass(&pass(image, 1, 0), 0, 1)
+}
+
+fn bloom(image: &Image, settings: &Bloom) -> Image {
+    let cut = |v: f32| (v - settings.threshold).max(0.0);
+    let bright = Image {
+        width: image.width,
+        height: image.height,
+        pixels: image
+            .pixels
+            .iter()
+            .map(|c| Color {
+                r: cut(c.r),
+                g: cut(c.g),
+                b: cut(c.b),
+            })
+            .collect(),
+    };
+    let glow = blur(&bright, settings.radius);
+    Image {
+        width: image.width,
+        height: image.height,
+        pixels: image
+            .pixels
+            .iter()
+            .zip(glow.pixels.iter())
+            .map(|(c, g)| *c + *g * settings.strength)
+            .collect(),
+    }
+}
+
+fn vignette(image: &Image, amount: f32) -> Image {
+    let (cx, cy, corner) = image.centre();
+    let mut pixels = image.pixels.clone();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let dx = (x as f32 + 0.5 - cx) / corner;
+            let dy = (y as f32 + 0.5 - cy) / corner;
+            // cos^4 falloff, squashed so the corners end up at 1 - amount
+            let cos2 = 1.0 / (1.0 + dx * dx + dy * dy);
+            let falloff = (cos2 * cos2 - 0.25) / 0.75;
+            pixels[y * image.width + x] = pixels[y * image.width + x] * (1.0 - amount * (1.0 - falloff));
+        }
+    }
+    Image { pixels, ..*image }
+}
+
+fn chromatic_aberration(image: &Image, amount: f32) -> Image {
+    let (cx, cy, _) = image.centre();
+    let mut pixels = Vec::with_capacity(image.pixels.len());
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+            // Sampling nearer the centre makes that channel spread outwards
+            let at = |scale: f32| image.sample(cx + (px - cx) / scale, cy + (py - cy) / scale);
+            pixels.push(Color {
+                r: at(1.0 + amount).r,
+                g: image.pixels[y * image.width + x].g,
+                b: at(1.0 - amount).b,
+            });
+        }
+    }
+    Image {
+        width: image.width,
+        height: image.height,
+        pixels,
+    }
+}
+
+impl PostProcess {
+    pub fn is_empty(&self) -> bool {
+        self.bloom.is_none() && self.vignette.is_none() && self.chromatic_aberration.is_none()
+    }
+
+    /// Runs in the order light meets them: lens fringes, glare, then the
+    /// fall off towards the edge of the sensor
+    pub fn apply(&self, film: &Film) -> Film {
+        let mut image = Image::from_film(film);
+        if let Some(amount) = self.chromatic_aberration {
+            image = chromatic_aberration(&image, amount);
+        }
+        if let Some(settings) = self.bloom {
+            image = bloom(&image, &settings);
+        }
+        if let Some(amount) = self.vignette {
+            image = vignette(&image, amount);
+        }
+        image.to_film()
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn grey(v: f32) -> Color {
+        Color { r: v, g: v, b: v }
+    }
+
+    #[test]
+    fn test_bloom_spreads_bright_pixels() {
+        let mut pixels = vec![grey(0.5); 21 * 21];
+        pixels[10 * 21 + 10] = grey(50.0);
+        let film = Film::from_pixels(21, 21, pixels);
+        let post = PostProcess {
+            bloom: Some(Bloom {
+                radius: 2.0,
+                ..Bloom::default()
+            }),
+            ..PostProcess::default()
+        };
+        let result = post.apply(&film);
+        assert!(result.get(12, 10).r > 0.5);
+        // Far away and below the threshold nothing changes
+        assert!((result.get(0, 0).r - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_vignette_and_fringes_leave_the_centre() {
+        let mut pixels = vec![grey(1.0); 9 * 9];
+        pixels[4 * 9 + 6] = Color {
+            r: 0.0,
+            g: 1.0,
+            b: 1.0,
+        };
+        let film = Film::from_pixels(9, 9, pixels);
+        let post = PostProcess {
+            vignette: Some(0.5),
+            chromatic_aberration: Some(0.3),
+            ..PostProcess::default()
+        };
+        let result = post.apply(&film);
+        assert!((result.get(4, 4).r - 1.0).abs() < 0.01);
+        assert!((result.get(0, 0).g - 0.5).abs() < 0.1);
+        // The red hole moves outwards
+        assert!(result.get(6, 4).r > result.get(7, 4).r);
+    }
+}
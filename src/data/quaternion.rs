@@ -0,0 +1,139 @@
+use std::f32::consts::PI;
+
+use Matrix4;
+use Point;
+
+/// Unit quaternion used for rotations that need to be interpolated
+#[derive(Debug, Clone, Copy)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+pub const NO_ROTATION: Quaternion = Quaternion {
+    w: 1.0,
+    x: 0.0,
+    y: 0.0,
+    z: 0.0,
+};
+
+impl Quaternion {
+    pub fn from_axis_angle(axis: Point, degrees: f32) -> Quaternion {
+        let a = axis.unit_vector();
+        let half = degrees * PI / 360.0;
+        let s = half.sin();
+        Quaternion {
+            w: half.cos(),
+            x: a.x * s,
+            y: a.y * s,
+            z: a.z * s,
+        }
+    }
+
+    pub fn dot(&self, o: &Quaternion) -> f32 {
+        self.w * o.w + self.x * o.x + self.y * o.y + self.z * o.z
+    }
+
+    fn scale(&self, f: f32) -> Quaternion {
+        Quaternion {
+            w: self.w * f,
+            x: self.x * f,
+            y: self.y * f,
+            z: self.z * f,
+        }
+    }
+
+    fn add(&self, o: &Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w + o.w,
+            x: self.x + o.x,
+            y: self.y + o.y,
+            z: self.z + o.z,
+        }
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        self.scale(1.0 / self.dot(self).sqrt())
+    }
+
+    /// Rotation of `self` followed by `o`
+    pub fn then(&self, o: &Quaternion) -> Quaternion {
+        Quaternion {
+            w: o.w * self.w - o.x * self.x - o.y * self.y - o.z * self.z,
+            x: o.w * self.x + o.x * self.w + o.y * self.z - o.z * self.y,
+            y: o.w * self.y - o.x * self.z + o.y * self.w + o.z * self.x,
+            z: o.w * self.z + o.x * self.y - o.y * self.x + o.z * self.w,
+        }
+    }
+
+    /// Spherical interpolation along the shortest arc
+    pub fn slerp(&self, other: &Quaternion, t: f32) -> Quaternion {
+        let mut cos = self.dot(other);
+        let mut end = *other;
+        if cos < 0.0 {
+            cos = -cos;
+            end = end.scale(-1.0);
+        }
+        if cos > 0.9995 {
+            // Nearly parallel: lerp is accurate and avoids dividing by ~0
+            return self.scale(1.0 - t).add(&end.scale(t)).normalize();
+        }
+        let theta = cos.acos();
+        let sin = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / sin;
+        let b = (t * theta).sin() / sin;
+        self.scale(a).add(&end.scale(b))
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        let Quaternion { w, x, y, z } = *self;
+        Matrix4 {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_slerp_matches_rotation() {
+        let up = Point {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        let start = NO_ROTATION;
+        let end = Quaternion::from_axis_angle(up, 120.0);
+        let half = start.slerp(&end, 0.5).to_matrix();
+        let expected = Matrix4::rotation(up, 60.0);
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((half.m[i][j] - expected.m[i][j]).abs() < 1e-5);
+            }
+        }
+    }
+}
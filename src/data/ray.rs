@@ -4,6 +4,9 @@ pub struct Ray {
     pub origin: Point,
     pub direction: Point,
     pub time: f32,
+    /// Nanometres of the one wavelength this path carries, 0 for plain RGB
+    /// until something dispersive picks one
+    pub wavelength: f32,
 }
 
 impl Ray {
@@ -0,0 +1,153 @@
+use CameraBuilder;
+use Point;
+
+/// The camera settings that can be animated
+#[derive(Debug, Clone, Copy)]
+pub struct CameraKey {
+    pub time: f32,
+    pub look_from: Point,
+    pub look_at: Point,
+    pub vfov: f32,
+    pub aperture: f32,
+}
+
+/// Camera keys sorted by time, interpolated linearly. Like
+/// AnimatedTransform the first or last key holds outside the keyed range.
+#[derive(Debug, Clone)]
+pub struct CameraTrack {
+    pub keys: Vec<CameraKey>,
+    pub up: Point,
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+impl CameraTrack {
+    pub fn new(mut keys: Vec<CameraKey>, up: Point) -> CameraTrack {
+        assert!(!keys.is_empty(), "Need at least one camera key");
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        CameraTrack { keys, up }
+    }
+
+    /// Orbit `look_at` once between `time0` and `time1` starting from `look_from`
+    pub fn turntable(
+        look_from: Point,
+        look_at: Point,
+        vfov: f32,
+        aperture: f32,
+        time0: f32,
+        time1: f32,
+    ) -> CameraTrack {
+        let offset = look_from - look_at;
+        let radius = (offset.x * offset.x + offset.z * offset.z).sqrt();
+        let start = offset.z.atan2(offset.x);
+        // Enough keys that linear interpolation stays close to the circle
+        let steps = 64;
+        let keys = (0..=steps)
+            .map(|i| {
+                let f = i as f32 / steps as f32;
+                let angle = start + f * 2.0 * std::f32::consts::PI;
+                CameraKey {
+                    time: lerp(time0, time1, f),
+                    look_from: look_at + Point {
+                        x: radius * angle.cos(),
+                        y: offset.y,
+                        z: radius * angle.sin(),
+                    },
+                    look_at,
+                    vfov,
+                    aperture,
+                }
+            }).collect();
+        CameraTrack::new(
+            keys,
+            Point {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        )
+    }
+
+    pub fn at(&self, time: f32) -> CameraKey {
+        let keys = &self.keys;
+        let last = keys[keys.len() - 1];
+        if time <= keys[0].time {
+            return keys[0];
+        }
+        if time >= last.time {
+            return last;
+        }
+        let i = keys.iter().position(|k| k.time > time).unwrap();
+        let (k0, k1) = (&keys[i - 1], &keys[i]);
+        let t = (time - k0.time) / (k1.time - k0.time);
+        CameraKey {
+            time,
+            look_from: k0.look_from + (k1.look_from - k0.look_from) * t,
+            look_at: k0.look_at + (k1.look_at - k0.look_at) * t,
+            vfov: lerp(k0.vfov, k1.vfov, t),
+            aperture: lerp(k0.aperture, k1.aperture, t),
+        }
+    }
+
+    /// `base` with the track applied for one frame. The camera is placed at
+    /// the middle of the shutter interval, rays still sample the whole
+    /// interval so objects blur.
+    pub fn builder(&self, base: &CameraBuilder, shutter: (f32, f32)) -> CameraBuilder {
+        let key = self.at((shutter.0 + shutter.1) / 2.0);
+        base.clone()
+            .look_from(key.look_from)
+            .look_at(key.look_at)
+            .up(self.up)
+            .vfov(key.vfov)
+            .aperture(key.aperture)
+            .shutter(shutter.0, shutter.1)
+    }
+}
+
+/// Inclusive range of frames. A shutter angle of 360 keeps the shutter open
+/// for the whole frame, 180 for half of it.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRange {
+    pub first: i32,
+    pub last: i32,
+    pub fps: f32,
+    pub shutter_angle: f32,
+}
+
+impl FrameRange {
+    pub fn shutter(&self, frame: i32) -> (f32, f32) {
+        let open = frame as f32 / self.fps;
+        let close = open + (self.shutter_angle / 360.0) / self.fps;
+        (open, close)
+    }
+
+    pub fn duration(&self) -> f32 {
+        (self.last - self.first + 1) as f32 / self.fps
+    }
+
+    pub fn file_name(&self, prefix: &str, frame: i32, extension: &str) -> String {
+        format!("{}_{:04}.{}", prefix, frame, extension)
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_shutter_interval() {
+        let range = FrameRange {
+            first: 0,
+            last: 47,
+            fps: 24.0,
+            shutter_angle: 180.0,
+        };
+        let (open, close) = range.shutter(12);
+        assert!((open - 0.5).abs() < 1e-6);
+        assert!((close - open - 1.0 / 48.0).abs() < 1e-6);
+        assert!((range.duration() - 2.0).abs() < 1e-6);
+        assert_eq!(range.file_name("out", 7, "ppm"), "out_0007.ppm");
+    }
+}
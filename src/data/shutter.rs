@@ -0,0 +1,119 @@
+use rnd;
+
+/// How far open the shutter is over the exposure. Ray times are drawn in
+/// proportion to it, so a triangle gives motion blur with soft ends.
+#[derive(Debug, Clone)]
+pub enum ShutterCurve {
+    Box,
+    Triangle,
+    /// Openness at evenly spaced points from open to close, joined linearly
+    Custom(CurveTable),
+}
+
+#[derive(Debug, Clone)]
+pub struct CurveTable {
+    values: Vec<f32>,
+    cdf: Vec<f32>,
+}
+
+impl CurveTable {
+    pub fn new(values: Vec<f32>) -> CurveTable {
+        assert!(values.len() >= 2, "Shutter curve needs at least 2 points");
+        assert!(values.iter().all(|v| *v >= 0.0), "Shutter curve can't be negative");
+        let mut total = 0.0;
+        let cdf: Vec<f32> = values
+            .windows(2)
+            .map(|w| {
+                total += (w[0] + w[1]) / 2.0;
+                total
+            }).collect();
+        assert!(total > 0.0, "Shutter curve is never open");
+        CurveTable { values, cdf }
+    }
+
+    fn sample(&self) -> f32 {
+        let target = rnd() * self.cdf[self.cdf.len() - 1];
+        let i = self
+            .cdf
+            .iter()
+            .position(|c| *c >= target)
+            .unwrap_or(self.cdf.len() - 1);
+        let below = if i == 0 { 0.0 } else { self.cdf[i - 1] };
+        let (a, b) = (self.values[i], self.values[i + 1]);
+        // Invert the area under a + (b - a) x between 0 and x
+        let area = target - below;
+        let x = if (b - a).abs() < 1e-6 {
+            area / a
+        } else {
+            (-a + (a * a + 2.0 * (b - a) * area).max(0.0).sqrt()) / (b - a)
+        };
+        (i as f32 + x.clamp(0.0, 1.0)) / (self.values.len() - 1) as f32
+    }
+}
+
+impl ShutterCurve {
+    /// Fraction of the way through the exposure
+    pub fn sample(&self) -> f32 {
+        match self {
+            ShutterCurve::Box => rnd(),
+            ShutterCurve::Triangle => {
+                let u = rnd();
+                if u < 0.5 {
+                    (u / 2.0).sqrt()
+                } else {
+                    1.0 - ((1.0 - u) / 2.0).sqrt()
+                }
+            }
+            ShutterCurve::Custom(table) => table.sample(),
+        }
+    }
+}
+
+/// Exposure from time0 to time1. With a rolling shutter each row gets the
+/// full exposure but starts later, the top row first and the bottom row
+/// `readout` later.
+#[derive(Debug, Clone)]
+pub struct Shutter {
+    pub time0: f32,
+    pub time1: f32,
+    pub curve: ShutterCurve,
+    pub readout: Option<f32>,
+}
+
+impl Shutter {
+    /// `t` is the height on the image from 0 (bottom) to 1 (top)
+    pub fn sample(&self, t: f32) -> f32 {
+        let row_start = match self.readout {
+            Some(readout) => (1.0 - t) * readout,
+            None => 0.0,
+        };
+        self.time0 + row_start + self.curve.sample() * (self.time1 - self.time0)
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_custom_curve_skips_closed_part() {
+        // Closed for the last third of the exposure
+        let curve = ShutterCurve::Custom(CurveTable::new(vec![1.0, 1.0, 0.0, 0.0]));
+        for _ in 0..1000 {
+            let t = curve.sample();
+            assert!((0.0..=2.0 / 3.0 + 1e-5).contains(&t));
+        }
+    }
+
+    #[test]
+    fn test_rolling_shutter_rows() {
+        let shutter = Shutter {
+            time0: 0.0,
+            time1: 0.0,
+            curve: ShutterCurve::Box,
+            readout: Some(0.5),
+        };
+        assert!((shutter.sample(1.0) - 0.0).abs() < 1e-6);
+        assert!((shutter.sample(0.0) - 0.5).abs() < 1e-6);
+    }
+}
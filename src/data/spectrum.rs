@@ -0,0 +1,348 @@
+use Color;
+
+/// The visible range we sample, in nanometres
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 720.0;
+
+pub fn sample_wavelength(u: f32) -> f32 {
+    LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+fn lobe(lambda: f32, mu: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+    let sigma = if lambda < mu {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 colour matching functions, the multi lobe fit from Wyman, Sloan
+/// and Shirley, "Simple Analytic Approximations to the CIE XYZ Color
+/// Matching Functions" (2013). Returned as x, y, z in a Color.
+pub fn cie_xyz(lambda: f32) -> Color {
+    Color {
+        r: 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        g: 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        b: 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    }
+}
+
+/// XYZ to linear sRGB primaries with a D65 white
+pub fn xyz_to_rgb(xyz: &Color) -> Color {
+    Color {
+        r: 3.2406 * xyz.r - 1.5372 * xyz.g - 0.4986 * xyz.b,
+        g: -0.9689 * xyz.r + 1.8758 * xyz.g + 0.0415 * xyz.b,
+        b: 0.0557 * xyz.r - 0.2040 * xyz.g + 1.0570 * xyz.b,
+    }
+}
+
+fn clamped_rgb(lambda: f32) -> Color {
+    let c = xyz_to_rgb(&cie_xyz(lambda));
+    Color {
+        r: c.r.max(0.0),
+        g: c.g.max(0.0),
+        b: c.b.max(0.0),
+    }
+}
+
+const BALANCE_STEPS: usize = 1000;
+
+lazy_static! {
+    /// Makes wavelength_to_rgb average to white over the sampled range
+    static ref BALANCE: Color = {
+        let mut sum = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+        };
+        for i in 0..BALANCE_STEPS {
+            sum += clamped_rgb(sample_wavelength((i as f32 + 0.5) / BALANCE_STEPS as f32));
+        }
+        let mean = sum / BALANCE_STEPS as f32;
+        Color {
+            r: 1.0 / mean.r,
+            g: 1.0 / mean.g,
+            b: 1.0 / mean.b,
+        }
+    };
+}
+
+/// Colour a path carrying only `lambda` contributes when wavelengths are
+/// picked uniformly. Out of gamut negatives are cut off and the rest is
+/// scaled so the average over all wavelengths is exactly white.
+pub fn wavelength_to_rgb(lambda: f32) -> Color {
+    clamped_rgb(lambda).mul(&BALANCE)
+}
+
+/// How many wavelengths each path carries in spectral mode
+pub const WAVELENGTHS: usize = 4;
+
+/// A hero wavelength plus others spread evenly through the range from it
+/// (Wilkie et al. 2014, "Hero Wavelength Spectral Sampling")
+#[derive(Debug, Clone, Copy)]
+pub struct SampledWavelengths {
+    pub lambda: [f32; WAVELENGTHS],
+    secondary_terminated: bool,
+}
+
+impl SampledWavelengths {
+    pub fn sample(u: f32) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f32 / WAVELENGTHS as f32) % 1.0;
+            *l = LAMBDA_MIN + offset * range;
+        }
+        SampledWavelengths {
+            lambda,
+            secondary_terminated: false,
+        }
+    }
+
+    /// Past a dispersive surface only the hero's direction is right. Returns
+    /// true the first time so the caller knows to drop the others.
+    pub fn terminate_secondary(&mut self) -> bool {
+        let first = !self.secondary_terminated;
+        self.secondary_terminated = true;
+        first
+    }
+
+    /// The one that decides where dispersive surfaces send the path
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+}
+
+/// Values of a spectrum at a path's sampled wavelengths
+#[derive(Debug, Clone, Copy)]
+pub struct SampledSpectrum {
+    pub values: [f32; WAVELENGTHS],
+}
+
+impl SampledSpectrum {
+    pub fn constant(v: f32) -> SampledSpectrum {
+        SampledSpectrum {
+            values: [v; WAVELENGTHS],
+        }
+    }
+
+    pub fn from_fn(wavelengths: &SampledWavelengths, f: &dyn Fn(f32) -> f32) -> SampledSpectrum {
+        let mut values = [0.0; WAVELENGTHS];
+        for (v, l) in values.iter_mut().zip(wavelengths.lambda.iter()) {
+            *v = f(*l);
+        }
+        SampledSpectrum { values }
+    }
+
+    pub fn mul(&self, rhs: &SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (v, r) in values.iter_mut().zip(rhs.values.iter()) {
+            *v *= r;
+        }
+        SampledSpectrum { values }
+    }
+
+    /// Scales down so nothing is over `max`, like clamp_radiance
+    pub fn clamp(&self, max: f32) -> SampledSpectrum {
+        let brightest = self.values.iter().cloned().fold(0.0, f32::max);
+        if brightest > max {
+            let mut values = self.values;
+            for v in values.iter_mut() {
+                *v *= max / brightest;
+            }
+            SampledSpectrum { values }
+        } else {
+            *self
+        }
+    }
+
+    /// Only the hero survives something dispersive, it now stands in for
+    /// the whole set
+    pub fn hero_only(&self) -> SampledSpectrum {
+        let mut values = [0.0; WAVELENGTHS];
+        values[0] = self.values[0] * WAVELENGTHS as f32;
+        SampledSpectrum { values }
+    }
+
+    /// Monte Carlo estimate of the CIE XYZ of this spectrum, Y = 1 for
+    /// a constant 1
+    pub fn to_xyz(&self, wavelengths: &SampledWavelengths) -> Color {
+        let mut sum = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+        };
+        for (v, l) in self.values.iter().zip(wavelengths.lambda.iter()) {
+            sum += cie_xyz(*l) * *v;
+        }
+        sum * ((LAMBDA_MAX - LAMBDA_MIN) / (WAVELENGTHS as f32 * *CIE_Y_INTEGRAL))
+    }
+
+    pub fn to_rgb(&self, wavelengths: &SampledWavelengths) -> Color {
+        xyz_to_rgb(&self.to_xyz(wavelengths))
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// RGB reflectance to a smooth spectrum. Blue, green and red basis curves
+/// that always add up to one, so white stays flat and anything in 0..1
+/// stays a physical reflectance. Colours come out a little desaturated.
+pub fn uplift(c: &Color, lambda: f32) -> f32 {
+    let blue = 1.0 - smoothstep(470.0, 530.0, lambda);
+    let red = smoothstep(560.0, 620.0, lambda);
+    c.b * blue + c.r * red + c.g * (1.0 - blue - red)
+}
+
+pub fn uplift_sampled(c: &Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+    SampledSpectrum::from_fn(wavelengths, &|l| uplift(c, l))
+}
+
+/// CIE D65 relative spectral power, 380 to 720nm every 10nm
+const D65: [f32; 35] = [
+    49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+    115.923, 108.811, 109.354, 107.802, 104.790, 107.689, 104.405, 104.046, 100.000, 96.3342,
+    95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778,
+    78.2842, 69.7213, 71.6091, 74.349, 61.604,
+];
+
+/// Planck's law in arbitrary units
+fn planck(lambda: f32, kelvin: f32) -> f32 {
+    let metres = f64::from(lambda) * 1e-9;
+    let c2 = 1.438_777e-2;
+    (1.0 / (metres.powi(5) * ((c2 / (metres * f64::from(kelvin))).exp() - 1.0))) as f32
+}
+
+/// Spectrum of the light in the scene
+#[derive(Debug, Clone, Copy)]
+pub enum Illuminant {
+    /// Equal energy
+    E,
+    /// Average daylight, what sRGB calls white
+    D65,
+    /// Incandescent tungsten, a 2856K blackbody
+    A,
+    Blackbody { kelvin: f32 },
+}
+
+impl Illuminant {
+    fn relative(&self, lambda: f32) -> f32 {
+        match self {
+            Illuminant::E => 1.0,
+            Illuminant::D65 => {
+                let x = ((lambda - LAMBDA_MIN) / 10.0).clamp(0.0, (D65.len() - 1) as f32);
+                let i = (x as usize).min(D65.len() - 2);
+                let t = x - i as f32;
+                D65[i] * (1.0 - t) + D65[i + 1] * t
+            }
+            Illuminant::A => planck(lambda, 2856.0),
+            Illuminant::Blackbody { kelvin } => planck(lambda, *kelvin),
+        }
+    }
+}
+
+const INTEGRAL_STEPS: usize = 340;
+
+fn integrate(f: &dyn Fn(f32) -> f32) -> f32 {
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / INTEGRAL_STEPS as f32;
+    (0..INTEGRAL_STEPS)
+        .map(|i| f(LAMBDA_MIN + (i as f32 + 0.5) * step) * step)
+        .sum()
+}
+
+lazy_static! {
+    static ref CIE_Y_INTEGRAL: f32 = integrate(&|l| cie_xyz(l).g);
+}
+
+/// An illuminant scaled to a luminance of 1, so swapping one for another
+/// changes the colour of the light but not how bright it is
+#[derive(Debug, Clone, Copy)]
+pub struct Emission {
+    pub illuminant: Illuminant,
+    scale: f32,
+}
+
+impl Emission {
+    pub fn new(illuminant: Illuminant) -> Emission {
+        let y = integrate(&|l| illuminant.relative(l) * cie_xyz(l).g);
+        Emission {
+            illuminant,
+            scale: *CIE_Y_INTEGRAL / y,
+        }
+    }
+
+    pub fn value(&self, lambda: f32) -> f32 {
+        self.illuminant.relative(lambda) * self.scale
+    }
+
+    /// Light of RGB colour `c` (relative to white) under this illuminant
+    pub fn sampled(&self, c: &Color, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum::from_fn(wavelengths, &|l| uplift(c, l) * self.value(l))
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_wavelengths_average_to_white() {
+        let n = 340;
+        let mut sum = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+        };
+        for i in 0..n {
+            sum += wavelength_to_rgb(sample_wavelength((i as f32 + 0.5) / n as f32));
+        }
+        let mean = sum / n as f32;
+        assert!(
+            (mean.r - 1.0).abs() < 0.01
+                && (mean.g - 1.0).abs() < 0.01
+                && (mean.b - 1.0).abs() < 0.01
+        );
+
+        let red = wavelength_to_rgb(650.0);
+        assert!(red.r > red.g && red.r > red.b);
+        let blue = wavelength_to_rgb(450.0);
+        assert!(blue.b > blue.r && blue.b > blue.g);
+    }
+
+    #[test]
+    fn test_white_under_d65_is_white() {
+        let d65 = Emission::new(Illuminant::D65);
+        let white = Color {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+        };
+        let n = 2000;
+        let mut sum = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+        };
+        for i in 0..n {
+            let wavelengths = SampledWavelengths::sample((i as f32 + 0.5) / n as f32);
+            sum += d65.sampled(&white, &wavelengths).to_rgb(&wavelengths);
+        }
+        let rgb = sum / n as f32;
+        for v in &[rgb.r, rgb.g, rgb.b] {
+            assert!((v - 1.0).abs() < 0.05, "{:?}", rgb);
+        }
+
+        // Tungsten is orange next to daylight
+        let a = Emission::new(Illuminant::A);
+        let warm = SampledWavelengths::sample(0.3);
+        let red_over_blue = |e: &Emission| e.value(650.0) / e.value(450.0);
+        assert!(red_over_blue(&a) > red_over_blue(&d65));
+        assert!(a.sampled(&white, &warm).values.iter().all(|v| *v > 0.0));
+    }
+}
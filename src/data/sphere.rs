@@ -6,6 +6,9 @@ use Material;
 use Point;
 use Ray;
 use Color;
+use Transform;
+use Instance;
+use Animated;
 
 fn hit<'a>(
     r: &Ray,
@@ -14,7 +17,7 @@ fn hit<'a>(
     radius: f32,
     material: &'a Material,
     center: &Point,
-) -> Option<Hit> {
+) -> Option<Hit<'a>> {
     let origin_less_center = r.origin - *center;
     let a = r.direction.dot(&r.direction);
     let b = origin_less_center.dot(&r.direction);
@@ -39,24 +42,20 @@ fn color_from_hit<'a>(
     center: &Point,
     radius: f32,
     temp: f32,
-) -> Option<Hit> {
-
-            let point = r.point_at_parameter(temp);
-            let normal = (point - *center) / radius;
-            let (u, v) = get_sphere_uv(normal);
-    let scattered = material.scatter(r, normal, point);
-    if let Some(scattered_ray) = scattered {
-        let albedo = material.get_albedo(&point, u, v);
-        Some(Hit {
-            color: albedo,
-            scattered_ray,
-            t: temp,
-        })
-    //let c = color(&scatter_ray, bound_box, depth + 1);
-    //c.mul(&albedo)
-    } else {
-        None
-    }
+) -> Option<Hit<'a>> {
+    let point = r.point_at_parameter(temp);
+    let normal = (point - *center) / radius;
+    let (u, v) = get_sphere_uv(normal);
+    Some(Hit {
+        color: material.get_albedo(&point, u, v),
+        point,
+        normal,
+        u,
+        v,
+        material,
+        object_id: 0,
+        t: temp,
+    })
 }
 
 fn get_sphere_uv(p: Point) -> (f32, f32) {
@@ -73,14 +72,22 @@ fn limit_value_for_trig(a: f32) -> f32 {
     a.max(-0.999).min(0.9999)
 }
 
-pub struct Hit {
+/// Where a ray landed. Scattering is left to the caller so wrappers like
+/// Transform can move the hit back into world space first.
+pub struct Hit<'a> {
     pub color: Color,
-    pub scattered_ray: Ray,
+    pub point: Point,
+    pub normal: Point,
+    pub u: f32,
+    pub v: f32,
+    pub material: &'a Material,
+    /// Set by the BVH leaf holding the object, instances get their own
+    pub object_id: usize,
     pub t: f32,
 }
 
 pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit>;
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>>;
     fn bounding_box(&self) -> BoundingBox;
 }
 
@@ -88,19 +95,28 @@ pub trait Hittable {
 pub enum SphereThing {
     S(Sphere),
     SM(SphereMoving),
+    TR(Transform),
+    I(Instance),
+    A(Animated),
 }
 
 impl Hittable for SphereThing {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         match self {
             SphereThing::S(s) => s.hit(r, t_min, t_max),
             SphereThing::SM(s) => s.hit(r, t_min, t_max),
+            SphereThing::TR(t) => t.hit(r, t_min, t_max),
+            SphereThing::I(i) => i.hit(r, t_min, t_max),
+            SphereThing::A(a) => a.hit(r, t_min, t_max),
         }
     }
     fn bounding_box(&self) -> BoundingBox {
         match self {
             SphereThing::S(s) => s.bounding_box(),
             SphereThing::SM(s) => s.bounding_box(),
+            SphereThing::TR(t) => t.bounding_box(),
+            SphereThing::I(i) => i.bounding_box(),
+            SphereThing::A(a) => a.bounding_box(),
         }
     }
 }
@@ -112,7 +128,7 @@ pub struct Sphere {
     pub material: Material,
 }
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         hit(r, t_min, t_max, self.radius, &self.material, &self.center)
     }
     fn bounding_box(&self) -> BoundingBox {
@@ -139,7 +155,7 @@ pub struct SphereMoving {
 }
 
 impl Hittable for SphereMoving {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         hit(
             r,
             t_min,
@@ -165,8 +181,10 @@ impl Hittable for SphereMoving {
 }
 
 impl SphereMoving {
+    /// Outside time0..time1 the sphere rests at the nearer end, which keeps
+    /// it inside its bounding box when a sequence runs past the keyframes
     fn get_center(&self, time: f32) -> Point {
-        let t_diff = (time - self.time0) / (self.time1 - self.time0);
+        let t_diff = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
         self.center0 + ((self.center1 - self.center0) * t_diff)
     }
 }
@@ -174,3 +192,52 @@ impl SphereMoving {
 pub struct SphereList {
     pub spheres: Vec<SphereThing>,
 }
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_moving_sphere_rests_after_last_keyframe() {
+        use Dielectric;
+
+        let origin = Point {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let sphere = SphereMoving {
+            center0: origin,
+            center1: Point {
+                x: 0.0,
+                y: 2.0,
+                z: 0.0,
+            },
+            radius: 0.5,
+            material: Material::Dielectric(Dielectric {
+                reflective_index: 1.5,
+                dispersion: None,
+            }),
+            time0: 0.0,
+            time1: 1.0,
+        };
+        // Looking along z at the height the sphere would have drifted to
+        let ray_at = |y: f32| Ray {
+            origin: Point {
+                x: 0.0,
+                y,
+                z: 5.0,
+            },
+            direction: Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            time: 2.0,
+            wavelength: 0.0,
+        };
+        assert!(sphere.hit(&ray_at(2.0), 0.001, f32::MAX).is_some());
+        assert!(sphere.hit(&ray_at(4.0), 0.001, f32::MAX).is_none());
+        assert!(sphere.bounding_box().hit(&ray_at(2.0)).is_some());
+    }
+}
@@ -162,7 +162,7 @@ impl ImageTexture {
         let x_pixel = (u) * width as f32;
         let y_pixel = (1.0 - v) * height as f32;
         let pixel = self.img.get_pixel(x_pixel as u32, y_pixel as u32);
-        let colors = pixel.data;
+        let colors = pixel.0;
         Color {
             r: colors[0] as f32 / 255.0,
             b: colors[2] as f32 / 255.0,
@@ -197,16 +197,18 @@ mod tests {
     #[test]
     fn test_noise() {
         // Tests nothing - just me playing
-        let mut ran_float = [255u8; 256];
+        let mut ran_float = [Point {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        }; 256];
         for i in 0..256 {
             if i % 2 == 0 {
-                ran_float[i] = 0;
-            } else {
-                ran_float[i] = 255;
+                ran_float[i] = ran_float[i] * 0.0;
             }
             //ran_float[i] = i as u8;
         }
-        let n = NoiseTexture { ran_float };
+        let n = NoiseTexture { ran_float, scale: 1.0 };
         for i in 0..100 {
             let a = n.noise(&Point {
                 x: 0.5,
@@ -0,0 +1,179 @@
+use Color;
+
+/// Squeezes linear HDR values into 0..1 before the transfer function
+#[derive(Debug, Clone, Copy)]
+pub enum ToneMapper {
+    /// Anything over 1 is cut off
+    Clamp,
+    Reinhard,
+    /// Reinhard where `white` maps to exactly 1
+    ExtendedReinhard { white: f32 },
+    /// John Hable's Uncharted 2 filmic curve
+    Hable,
+    /// Stephen Hill's fit of the ACES RRT and sRGB ODT
+    Aces,
+}
+
+/// Encoding from linear 0..1 to display values
+#[derive(Debug, Clone, Copy)]
+pub enum Transfer {
+    /// Plain square root, what this renderer always used to do
+    Gamma2,
+    Srgb,
+}
+
+fn hable_partial(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn hable(x: f32) -> f32 {
+    let exposure_bias = 2.0;
+    let white = 11.2;
+    hable_partial(x * exposure_bias) / hable_partial(white)
+}
+
+fn mat_mul(m: &[[f32; 3]; 3], c: &Color) -> Color {
+    Color {
+        r: m[0][0] * c.r + m[0][1] * c.g + m[0][2] * c.b,
+        g: m[1][0] * c.r + m[1][1] * c.g + m[1][2] * c.b,
+        b: m[2][0] * c.r + m[2][1] * c.g + m[2][2] * c.b,
+    }
+}
+
+// sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+const ACES_INPUT: [[f32; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+// ODT_SAT => XYZ => D60_2_D65 => sRGB
+const ACES_OUTPUT: [[f32; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn rrt_and_odt_fit(x: f32) -> f32 {
+    let a = x * (x + 0.024_578_6) - 0.000_090_537;
+    let b = x * (0.983_729 * x + 0.432_951) + 0.238_081;
+    a / b
+}
+
+fn aces(c: &Color) -> Color {
+    let v = mat_mul(&ACES_INPUT, c);
+    let v = Color {
+        r: rrt_and_odt_fit(v.r),
+        g: rrt_and_odt_fit(v.g),
+        b: rrt_and_odt_fit(v.b),
+    };
+    mat_mul(&ACES_OUTPUT, &v)
+}
+
+fn per_channel(c: &Color, f: &dyn Fn(f32) -> f32) -> Color {
+    Color {
+        r: f(c.r),
+        g: f(c.g),
+        b: f(c.b),
+    }
+}
+
+impl ToneMapper {
+    pub fn apply(&self, c: &Color) -> Color {
+        match self {
+            ToneMapper::Clamp => *c,
+            ToneMapper::Reinhard => per_channel(c, &|x| x / (1.0 + x)),
+            ToneMapper::ExtendedReinhard { white } => {
+                let w2 = white * white;
+                per_channel(c, &|x| x * (1.0 + x / w2) / (1.0 + x))
+            }
+            ToneMapper::Hable => per_channel(c, &hable),
+            ToneMapper::Aces => aces(c),
+        }
+    }
+}
+
+impl Transfer {
+    pub fn encode(&self, x: f32) -> f32 {
+        match self {
+            Transfer::Gamma2 => x.sqrt(),
+            Transfer::Srgb => {
+                if x <= 0.003_130_8 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                }
+            }
+        }
+    }
+}
+
+/// Linear radiance to 8 bit display values: tone map, clamp, encode
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayTransform {
+    pub tone_mapper: ToneMapper,
+    pub transfer: Transfer,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        DisplayTransform {
+            tone_mapper: ToneMapper::Clamp,
+            transfer: Transfer::Srgb,
+        }
+    }
+}
+
+impl DisplayTransform {
+    fn channel_byte(&self, x: f32) -> u8 {
+        // clamp passes NaN straight through so check for it first
+        let x = if x.is_nan() { 0.0 } else { x.clamp(0.0, 1.0) };
+        (255.99 * self.transfer.encode(x)) as u8
+    }
+
+    pub fn bytes(&self, c: &Color) -> [u8; 3] {
+        let c = self.tone_mapper.apply(c);
+        [self.channel_byte(c.r), self.channel_byte(c.g), self.channel_byte(c.b)]
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_bright_values_do_not_wrap() {
+        let bright = Color {
+            r: 3.0,
+            g: 1.0,
+            b: 0.5,
+        };
+        for tone_mapper in &[
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::ExtendedReinhard { white: 3.0 },
+            ToneMapper::Hable,
+            ToneMapper::Aces,
+        ] {
+            let display = DisplayTransform {
+                tone_mapper: *tone_mapper,
+                transfer: Transfer::Srgb,
+            };
+            let [r, g, b] = display.bytes(&bright);
+            assert!(r >= g && g >= b, "{:?} changed the channel order", tone_mapper);
+        }
+        let white = DisplayTransform {
+            tone_mapper: ToneMapper::ExtendedReinhard { white: 3.0 },
+            transfer: Transfer::Srgb,
+        };
+        assert_eq!(white.bytes(&bright)[0], 255);
+    }
+
+    #[test]
+    fn test_srgb_transfer() {
+        assert_eq!(Transfer::Srgb.encode(0.0), 0.0);
+        assert!((Transfer::Srgb.encode(1.0) - 1.0).abs() < 1e-5);
+        assert!((Transfer::Srgb.encode(0.18) - 0.4613).abs() < 1e-3);
+    }
+}
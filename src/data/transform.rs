@@ -0,0 +1,387 @@
+use std::f32;
+
+use BoundingBox;
+use surrounding_box;
+use Hit;
+use Hittable;
+use Matrix4;
+use Point;
+use Ray;
+use SphereThing;
+use IDENTITY;
+use Quaternion;
+
+/// A matrix together with its inverse so we only invert once per object
+#[derive(Debug, Clone, Copy)]
+pub struct Affine {
+    pub matrix: Matrix4,
+    pub inverse: Matrix4,
+}
+
+pub const NO_TRANSFORM: Affine = Affine {
+    matrix: IDENTITY,
+    inverse: IDENTITY,
+};
+
+impl Affine {
+    pub fn new(matrix: Matrix4) -> Affine {
+        Affine {
+            matrix,
+            inverse: matrix.inverse().expect("Transform matrix is not invertible"),
+        }
+    }
+
+    // Each of these applies the new transform after the existing one
+    pub fn then(&self, other: &Affine) -> Affine {
+        Affine {
+            matrix: other.matrix.mul(&self.matrix),
+            inverse: self.inverse.mul(&other.inverse),
+        }
+    }
+    pub fn translate(&self, by: Point) -> Affine {
+        self.then(&Affine::new(Matrix4::translation(by)))
+    }
+    pub fn rotate(&self, axis: Point, degrees: f32) -> Affine {
+        self.then(&Affine::new(Matrix4::rotation(axis, degrees)))
+    }
+    pub fn scale(&self, by: Point) -> Affine {
+        self.then(&Affine::new(Matrix4::scaling(by)))
+    }
+
+    /// Scale, then rotate, then translate. The inverse is built directly
+    /// so this is cheap enough to call for every ray.
+    pub fn from_parts(translation: Point, rotation: &Quaternion, scale: Point) -> Affine {
+        let rot = rotation.to_matrix();
+        let matrix = Matrix4::translation(translation)
+            .mul(&rot)
+            .mul(&Matrix4::scaling(scale));
+        let inverse = Matrix4::scaling(Point {
+            x: 1.0 / scale.x,
+            y: 1.0 / scale.y,
+            z: 1.0 / scale.z,
+        }).mul(&rot.transpose())
+        .mul(&Matrix4::translation(translation * -1.0));
+        Affine { matrix, inverse }
+    }
+
+    pub fn ray_to_object(&self, r: &Ray) -> Ray {
+        Ray {
+            origin: self.inverse.transform_point(&r.origin),
+            direction: self.inverse.transform_vector(&r.direction),
+            time: r.time,
+            wavelength: r.wavelength,
+        }
+    }
+
+    /// The ray direction is not normalised so t is the same in both spaces.
+    /// Normals use the inverse transpose to stay perpendicular under scaling.
+    pub fn hit_to_world<'a>(&self, hit: Hit<'a>) -> Hit<'a> {
+        let normal = self
+            .inverse
+            .transpose()
+            .transform_vector(&hit.normal)
+            .unit_vector();
+        Hit {
+            point: self.matrix.transform_point(&hit.point),
+            normal,
+            ..hit
+        }
+    }
+
+    pub fn box_to_world(&self, b: &BoundingBox) -> BoundingBox {
+        let mut point1 = Point {
+            x: f32::MAX,
+            y: f32::MAX,
+            z: f32::MAX,
+        };
+        let mut point2 = Point {
+            x: f32::MIN,
+            y: f32::MIN,
+            z: f32::MIN,
+        };
+        for corner in 0..8 {
+            let c = Point {
+                x: if corner & 1 == 0 { b.point1.x } else { b.point2.x },
+                y: if corner & 2 == 0 { b.point1.y } else { b.point2.y },
+                z: if corner & 4 == 0 { b.point1.z } else { b.point2.z },
+            };
+            let p = self.matrix.transform_point(&c);
+            point1 = Point {
+                x: point1.x.min(p.x),
+                y: point1.y.min(p.y),
+                z: point1.z.min(p.z),
+            };
+            point2 = Point {
+                x: point2.x.max(p.x),
+                y: point2.y.max(p.y),
+                z: point2.z.max(p.z),
+            };
+        }
+        BoundingBox { point1, point2 }
+    }
+}
+
+/// Places any hittable in the world via an affine transform. The object is
+/// intersected in its own space so textures stick to it as it moves.
+#[derive(Clone)]
+pub struct Transform {
+    pub affine: Affine,
+    pub thing: Box<SphereThing>,
+}
+
+impl Transform {
+    pub fn new(thing: SphereThing, affine: Affine) -> Transform {
+        Transform {
+            affine,
+            thing: Box::new(thing),
+        }
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let local = self.affine.ray_to_object(r);
+        self.thing
+            .hit(&local, t_min, t_max)
+            .map(|h| self.affine.hit_to_world(h))
+    }
+    fn bounding_box(&self) -> BoundingBox {
+        self.affine.box_to_world(&self.thing.bounding_box())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Point,
+    pub rotation: Quaternion,
+    pub scale: Point,
+}
+
+/// Keyframes sorted by time. Translation and scale are interpolated
+/// linearly and rotation with slerp. Outside the keyed range the first or
+/// last key holds.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    pub keyframes: Vec<Keyframe>,
+}
+
+// Samples per keyframe segment when sweeping the bounding box
+const MOTION_BOX_STEPS: usize = 16;
+
+impl AnimatedTransform {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> AnimatedTransform {
+        assert!(!keyframes.is_empty(), "Need at least one keyframe");
+        keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        AnimatedTransform { keyframes }
+    }
+
+    pub fn at(&self, time: f32) -> Affine {
+        let keys = &self.keyframes;
+        let first = &keys[0];
+        let last = &keys[keys.len() - 1];
+        if time <= first.time {
+            return Affine::from_parts(first.translation, &first.rotation, first.scale);
+        }
+        if time >= last.time {
+            return Affine::from_parts(last.translation, &last.rotation, last.scale);
+        }
+        let i = keys.iter().position(|k| k.time > time).unwrap();
+        let (k0, k1) = (&keys[i - 1], &keys[i]);
+        let t = (time - k0.time) / (k1.time - k0.time);
+        Affine::from_parts(
+            k0.translation + (k1.translation - k0.translation) * t,
+            &k0.rotation.slerp(&k1.rotation, t),
+            k0.scale + (k1.scale - k0.scale) * t,
+        )
+    }
+
+    /// Union of the box at many points in time over every segment. It is
+    /// padded slightly as rotating corners bow out between samples.
+    pub fn sweep_box(&self, b: &BoundingBox) -> BoundingBox {
+        let mut result = self.at(self.keyframes[0].time).box_to_world(b);
+        for pair in self.keyframes.windows(2) {
+            for step in 1..=MOTION_BOX_STEPS {
+                let t = pair[0].time
+                    + (pair[1].time - pair[0].time) * step as f32 / MOTION_BOX_STEPS as f32;
+                result = surrounding_box(&result, &self.at(t).box_to_world(b));
+            }
+        }
+        let pad = (result.point2 - result.point1) * 0.01;
+        BoundingBox {
+            point1: result.point1 - pad,
+            point2: result.point2 + pad,
+        }
+    }
+}
+
+/// Any hittable moving along keyframes. The ray's time picks the transform
+/// so motion blur comes from the camera's shutter interval.
+#[derive(Clone)]
+pub struct Animated {
+    pub motion: AnimatedTransform,
+    pub thing: Box<SphereThing>,
+}
+
+impl Animated {
+    pub fn new(thing: SphereThing, motion: AnimatedTransform) -> Animated {
+        Animated {
+            motion,
+            thing: Box::new(thing),
+        }
+    }
+}
+
+impl Hittable for Animated {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let affine = self.motion.at(r.time);
+        let local = affine.ray_to_object(r);
+        self.thing
+            .hit(&local, t_min, t_max)
+            .map(|h| affine.hit_to_world(h))
+    }
+    fn bounding_box(&self) -> BoundingBox {
+        self.motion.sweep_box(&self.thing.bounding_box())
+    }
+}
+
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use Dielectric;
+    #[allow(unused_imports)]
+    use Material;
+    #[allow(unused_imports)]
+    use Sphere;
+    #[allow(unused_imports)]
+    use NO_ROTATION;
+    #[allow(unused_imports)]
+    use rnd;
+
+    #[allow(dead_code)]
+    fn unit_sphere() -> SphereThing {
+        SphereThing::S(Sphere {
+            center: Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            radius: 1.0,
+            material: Material::Dielectric(Dielectric {
+                reflective_index: 1.5,
+                dispersion: None,
+            }),
+        })
+    }
+
+    #[test]
+    fn test_squashed_sphere_normal() {
+        let squashed = Transform::new(
+            unit_sphere(),
+            NO_TRANSFORM.scale(Point {
+                x: 1.0,
+                y: 0.5,
+                z: 1.0,
+            }),
+        );
+        // (0.6, 0.8, 0) on the unit sphere ends up here on the ellipsoid
+        let p = Point {
+            x: 0.6,
+            y: 0.4,
+            z: 0.0,
+        };
+        // Gradient of x^2 + (y / 0.5)^2 + z^2
+        let expected = Point {
+            x: p.x,
+            y: p.y * 4.0,
+            z: p.z,
+        }
+        .unit_vector();
+        let r = Ray {
+            origin: p + expected * 3.0,
+            direction: expected * -1.0,
+            time: 0.0,
+            wavelength: 0.0,
+        };
+        let hit = squashed.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((hit.point - p).length() < 1e-4);
+        assert!((hit.normal - expected).length() < 1e-4);
+        assert!((hit.t - 3.0).abs() < 1e-4);
+    }
+
+    #[allow(dead_code)]
+    fn key(time: f32, x: f32, rotation: Quaternion, scale: f32) -> Keyframe {
+        Keyframe {
+            time,
+            translation: Point { x, y: 0.0, z: 0.0 },
+            rotation,
+            scale: Point {
+                x: scale,
+                y: scale,
+                z: scale,
+            },
+        }
+    }
+
+    #[test]
+    fn test_animated_holds_and_interpolates() {
+        let motion = AnimatedTransform::new(vec![
+            key(2.0, 4.0, NO_ROTATION, 1.0),
+            key(1.0, 2.0, NO_ROTATION, 1.0),
+            key(3.0, 4.0, NO_ROTATION, 3.0),
+        ]);
+        let origin = Point {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let unit_x = Point {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let place = |time: f32, p: &Point| motion.at(time).matrix.transform_point(p).x;
+        // Before the first key and after the last they hold
+        assert!((place(0.0, &origin) - 2.0).abs() < 1e-5);
+        assert!((place(10.0, &unit_x) - 7.0).abs() < 1e-5);
+        // Keys were given out of order, between them it is linear
+        assert!((place(1.5, &origin) - 3.0).abs() < 1e-5);
+        assert!((place(2.5, &unit_x) - 6.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_sweep_box_holds_the_whole_motion() {
+        let up = Point {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        // Half a turn in one segment swings the box right round the axis
+        let motion = AnimatedTransform::new(vec![
+            key(0.0, 0.0, NO_ROTATION, 1.0),
+            key(1.0, 1.0, Quaternion::from_axis_angle(up, 180.0), 1.5),
+            key(2.0, 0.0, Quaternion::from_axis_angle(up, 90.0), 1.0),
+        ]);
+        let b = BoundingBox {
+            point1: Point {
+                x: 2.0,
+                y: -0.5,
+                z: -0.5,
+            },
+            point2: Point {
+                x: 3.0,
+                y: 0.5,
+                z: 0.5,
+            },
+        };
+        let swept = motion.sweep_box(&b);
+        for _ in 0..1000 {
+            let at = motion.at(rnd() * 2.0).box_to_world(&b);
+            for i in 0..3 {
+                assert!(at.point1.nth(i) >= swept.point1.nth(i));
+                assert!(at.point2.nth(i) <= swept.point2.nth(i));
+            }
+        }
+    }
+}
@@ -16,18 +16,7 @@ fn len(a: f32, b: f32, c: f32) -> f32 {
     (a * a + b * b + c * c).sqrt()
 }
 
-fn to_bytes(a: f32) -> u8 {
-    (255.99 * a.sqrt()) as u8
-}
 impl Color {
-    pub fn as_color_str(&self) -> String {
-        format!(
-            "{} {} {}\n",
-            to_bytes(self.r),
-            to_bytes(self.g),
-            to_bytes(self.b)
-        )
-    }
     pub fn mul(&self, rhs: &Color) -> Color {
         Color {
             r: self.r * rhs.r,
@@ -4,6 +4,8 @@ use std::cell::RefCell;
 use std::f32;
 use std::fs::File;
 use std::io::prelude::*;
+use std::sync::Arc;
+use std::sync::Mutex;
 
 use data::bounding::*;
 use data::material::*;
@@ -13,6 +15,21 @@ use data::textures::*;
 use data::vec3::*;
 use data::camera::*;
 use data::layouts::sphere_layout::*;
+use data::matrix::*;
+use data::transform::*;
+use data::quaternion::*;
+use data::sequence::*;
+use data::aperture::*;
+use data::shutter::*;
+use data::exposure::*;
+use data::tonemap::*;
+use data::film::*;
+use data::aov::*;
+use data::denoise::*;
+use data::firefly::*;
+use data::postprocess::*;
+use data::microfacet::*;
+use data::spectrum::*;
 
 pub mod data;
 
@@ -31,127 +48,723 @@ pub fn rnd() -> f32 {
     //0.4
 }
 
-fn color(r: &Ray, bound_box: &BvhBox, depth: u8) -> Color {
+fn sky(r: &Ray) -> Color {
+    let ud = r.direction.unit_vector();
+    let t = (ud.y + 1.0) * 0.5;
+    let init_c = 1.0 - t;
+    Color {
+        r: init_c,
+        g: init_c,
+        b: init_c,
+    } + Color {
+        r: 0.5 * t,
+        g: 0.7 * t,
+        b: 1.0 * t,
+    }
+}
+
+fn shade(hit: &Hit, r: &Ray, bound_box: &BvhBox, depth: u8, fireflies: &Fireflies) -> Color {
+    match hit.material.scatter(r, hit) {
+        Some(scatter) => {
+            let c = color(&scatter.ray, bound_box, depth + 1, fireflies);
+            c.mul(&scatter.attenuation)
+        }
+        None => NO_COLOR,
+    }
+}
+
+fn color(r: &Ray, bound_box: &BvhBox, depth: u8, fireflies: &Fireflies) -> Color {
     if depth >= 50 {
         return NO_COLOR;
     }
 
-    match bound_box.dig(r, f32::MAX) {
-        Some(hit) => {
-            let c = color(&hit.scattered_ray, bound_box, depth + 1);
-            c.mul(&hit.color)
-        },
-        None => {
-            let ud = r.direction.unit_vector();
-            let t = (ud.y + 1.0) * 0.5;
-            let init_c = 1.0 - t;
-            Color {
-                r: init_c,
-                g: init_c,
-                b: init_c,
-            } + Color {
-                r: 0.5 * t,
-                g: 0.7 * t,
-                b: 1.0 * t,
+    let c = match bound_box.dig(r, f32::MAX) {
+        Some(hit) => shade(&hit, r, bound_box, depth, fireflies),
+        None => sky(r),
+    };
+    if depth > 0 {
+        fireflies.clamp_indirect(c)
+    } else {
+        c
+    }
+}
+
+/// The passes for a first hit, the light is filled in by the caller
+fn first_hit_sample(hit: &Hit, r: &Ray) -> AovSample {
+    AovSample {
+        albedo: hit.color,
+        normal: hit.normal,
+        depth: hit.t * r.direction.length(),
+        position: hit.point,
+        object_id: id_color(hit.object_id),
+        material_id: id_color(hit.material.id()),
+        direct: NO_COLOR,
+        indirect: NO_COLOR,
+    }
+}
+
+/// Same as color but keeps what the first hit saw for the extra passes
+fn color_with_aovs(r: &Ray, bound_box: &BvhBox, fireflies: &Fireflies) -> AovSample {
+    let hit = match bound_box.dig(r, f32::MAX) {
+        Some(hit) => hit,
+        None => return AovSample::background(sky(r)),
+    };
+    let mut sample = first_hit_sample(&hit, r);
+    if let Some(scatter) = hit.material.scatter(r, &hit) {
+        match bound_box.dig(&scatter.ray, f32::MAX) {
+            Some(next) => {
+                let c = fireflies.clamp_indirect(shade(&next, &scatter.ray, bound_box, 1, fireflies));
+                sample.indirect = c.mul(&scatter.attenuation)
             }
+            None => sample.direct = sky(&scatter.ray).mul(&scatter.attenuation),
         }
     }
+    sample
 }
 
-fn calc_pixel(data: &(i32, i32, &Camera), bvh_box: &mut BvhBox) -> Color {
-    let i = data.0;
-    let j = data.1;
-    let cam = data.2;
-    let mut col = Color {
-        r: 0.0,
-        g: 0.0,
-        b: 0.0,
-    };
+fn sky_spectral(r: &Ray, emission: &Emission, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+    emission.sampled(&sky(r), wavelengths)
+}
+
+/// Light arriving back along a scattered ray, weighted by the RGB
+/// attenuation turned into a spectrum
+fn bounce_spectral(
+    material: &Material,
+    attenuation: &Color,
+    wavelengths: &SampledWavelengths,
+    incoming: &dyn Fn(&SampledWavelengths) -> SampledSpectrum,
+) -> SampledSpectrum {
+    let mut wavelengths = *wavelengths;
+    let dispersed = material.is_dispersive() && wavelengths.terminate_secondary();
+    let c = incoming(&wavelengths).mul(&uplift_sampled(attenuation, &wavelengths));
+    if dispersed {
+        c.hero_only()
+    } else {
+        c
+    }
+}
+
+fn shade_spectral(
+    hit: &Hit,
+    r: &Ray,
+    bound_box: &BvhBox,
+    depth: u8,
+    fireflies: &Fireflies,
+    emission: &Emission,
+    wavelengths: &SampledWavelengths,
+) -> SampledSpectrum {
+    match hit.material.scatter(r, hit) {
+        Some(scatter) => bounce_spectral(hit.material, &scatter.attenuation, wavelengths, &|w| {
+            color_spectral(&scatter.ray, bound_box, depth + 1, fireflies, emission, w)
+        }),
+        None => SampledSpectrum::constant(0.0),
+    }
+}
 
-    for _s in 0..NS {
-        let u = (i as f32 + rnd()) / NX as f32;
-        let v = (j as f32 + rnd()) / NY as f32;
+/// Same as color, but the path carries a few wavelengths instead of RGB
+fn color_spectral(
+    r: &Ray,
+    bound_box: &BvhBox,
+    depth: u8,
+    fireflies: &Fireflies,
+    emission: &Emission,
+    wavelengths: &SampledWavelengths,
+) -> SampledSpectrum {
+    if depth >= 50 {
+        return SampledSpectrum::constant(0.0);
+    }
+
+    let c = match bound_box.dig(r, f32::MAX) {
+        Some(hit) => shade_spectral(&hit, r, bound_box, depth, fireflies, emission, wavelengths),
+        None => sky_spectral(r, emission, wavelengths),
+    };
+    match fireflies.max_indirect {
+        Some(max) if depth > 0 => c.clamp(max),
+        _ => c,
+    }
+}
 
-        let ray = cam.get_ray(u, v);
-        col += color(&ray, bvh_box, 0);
+/// Same as color_with_aovs for spectral paths, light passes go to RGB as
+/// they are written
+fn spectral_with_aovs(
+    r: &Ray,
+    bound_box: &BvhBox,
+    fireflies: &Fireflies,
+    emission: &Emission,
+    wavelengths: &SampledWavelengths,
+) -> AovSample {
+    let hit = match bound_box.dig(r, f32::MAX) {
+        Some(hit) => hit,
+        None => return AovSample::background(sky_spectral(r, emission, wavelengths).to_rgb(wavelengths)),
+    };
+    let mut sample = first_hit_sample(&hit, r);
+    if let Some(scatter) = hit.material.scatter(r, &hit) {
+        let next = bound_box.dig(&scatter.ray, f32::MAX);
+        let c = bounce_spectral(hit.material, &scatter.attenuation, wavelengths, &|w| match &next {
+            Some(next) => {
+                let c = shade_spectral(next, &scatter.ray, bound_box, 1, fireflies, emission, w);
+                fireflies.max_indirect.map_or(c, |max| c.clamp(max))
+            }
+            None => sky_spectral(&scatter.ray, emission, w),
+        });
+        match next {
+            Some(_) => sample.indirect = c.to_rgb(wavelengths),
+            None => sample.direct = c.to_rgb(wavelengths),
+        }
     }
-    col / NS as f32
+    sample
+}
+
+/// Everything about how each camera sample is traced and kept
+#[derive(Clone, Copy)]
+struct Sampling {
+    /// Camera samples per pixel
+    samples: i32,
+    filter: Filter,
+    fireflies: Fireflies,
+    /// Trace spectra lit by this instead of RGB
+    spectral: Option<Emission>,
 }
 
-fn spheres_to_bounding_box(spheres: Vec<SphereThing>) -> BvhBox {
-    let mut bounds = vec![];
-    for a in spheres {
-        let mut b = a.bounding_box();
-        bounds.push(BvhLeaf { boxx: b, has_a: a });
+/// Splats jittered samples from pixel (i, j) into the film, whose bottom
+/// row is row `first` of the image. With outlier rejection on they are held
+/// back until the whole pixel has been sampled.
+fn calc_pixel(
+    i: i32,
+    j: i32,
+    first: i32,
+    cam: &dyn CameraModel,
+    bvh_box: &mut BvhBox,
+    layers: &mut Layers,
+    sampling: &Sampling,
+) {
+    let (filter, fireflies) = (&sampling.filter, &sampling.fireflies);
+    let mut held = vec![];
+    for _s in 0..sampling.samples {
+        let x = i as f32 + rnd();
+        let y = j as f32 + rnd();
+
+        let with_aovs = !layers.aovs.is_empty();
+        let sample = match (cam.get_ray(x / NX as f32, y / NY as f32), &sampling.spectral) {
+            (Some(mut ray), Some(emission)) => {
+                let wavelengths = SampledWavelengths::sample(rnd());
+                ray.wavelength = wavelengths.hero();
+                if with_aovs {
+                    spectral_with_aovs(&ray, bvh_box, fireflies, emission, &wavelengths)
+                } else {
+                    let c = color_spectral(&ray, bvh_box, 0, fireflies, emission, &wavelengths);
+                    AovSample::background(c.to_rgb(&wavelengths))
+                }
+            }
+            (Some(ray), None) if with_aovs => color_with_aovs(&ray, bvh_box, fireflies),
+            // Only the beauty is kept so the whole colour may as well go in direct
+            (Some(ray), None) => AovSample::background(color(&ray, bvh_box, 0, fireflies)),
+            (None, _) => AovSample::background(NO_COLOR),
+        };
+        if fireflies.reject_sigmas.is_some() {
+            held.push((x, y, sample));
+        } else {
+            layers.add_aov_sample(x, y - first as f32, &sample, filter);
+        }
+    }
+
+    let beauty: Vec<Color> = held.iter().map(|(_, _, s)| s.direct + s.indirect).collect();
+    for ((x, y, sample), keep) in held.iter().zip(fireflies.keep(&beauty)) {
+        if keep {
+            layers.add_aov_sample(*x, *y - first as f32, sample, filter);
+        }
     }
-    get_bvh_box(&mut bounds)
 }
 
 const NX: i32 = 800;
 const NY: i32 = 400;
+/// Samples per pixel unless --samples says otherwise
 const NS: i32 = 100;
+/// Rows each thread renders at a time
+const BAND_ROWS: i32 = 8;
 
-fn main() -> std::io::Result<()> {
-    println!("Hello, world!");
-    let mut buffer = File::create("out.ppm")?;
-    buffer.write_all(format!("P3\n{} {}\n255\n", NX, NY).as_bytes())?;
-
-    let cam = get_camera(
-        Point {
-            x: 13.0,
-            y: 2.0,
-            z: 3.0,
+fn render(cam: &dyn CameraModel, bound_box: &BvhBox, aovs: &[Aov], sampling: &Sampling) -> Layers {
+    // Do fancy thread local storage of the BVH boxes
+    thread_local!(static STORE: RefCell<Option<BvhBox>> = RefCell::new(None));
+
+    // Each band of rows is rendered into its own small film, with room
+    // above and below for the filter to reach into, then added to the image
+    let margin = sampling.filter.radius().ceil() as i32 + 1;
+    let image = Mutex::new(Layers::new(NX as usize, NY as usize, aovs));
+    (0..(NY + BAND_ROWS - 1) / BAND_ROWS)
+        .into_par_iter()
+        .for_each(|band| {
+            let first = band * BAND_ROWS - margin;
+            let rows = BAND_ROWS + 2 * margin;
+            let mut layers = Layers::new(NX as usize, rows as usize, aovs);
+            STORE.with(|bvh| {
+                let mut local_bvh = bvh.borrow_mut();
+                if local_bvh.is_none() {
+                    *local_bvh = Some(bound_box.clone());
+                }
+                for j in band * BAND_ROWS..((band + 1) * BAND_ROWS).min(NY) {
+                    for i in 0..NX {
+                        calc_pixel(i, j, first, cam, local_bvh.as_mut().unwrap(), &mut layers, sampling);
+                    }
+                }
+            });
+            image.lock().unwrap().merge_at(&layers, i64::from(first));
+        });
+    image.into_inner().unwrap()
+}
+
+fn write_ppm(file_name: &str, film: &Film, to_bytes: &dyn Fn(&Color) -> [u8; 3]) -> std::io::Result<()> {
+    let mut buffer = File::create(file_name)?;
+    buffer.write_all(format!("P3\n{} {}\n255\n", film.width, film.height).as_bytes())?;
+    for row in film.pixels() {
+        let [r, g, b] = to_bytes(&row);
+        buffer.write_all(format!("{} {} {}\n", r, g, b).as_bytes())?;
+    }
+    buffer.flush()
+}
+
+/// Portable float map, keeps the values as they are. Rows go bottom to top
+/// which matches the film.
+fn write_pfm(file_name: &str, film: &Film) -> std::io::Result<()> {
+    let mut buffer = File::create(file_name)?;
+    buffer.write_all(format!("PF\n{} {}\n-1.0\n", film.width, film.height).as_bytes())?;
+    for c in film.resolved() {
+        for v in &[c.r, c.g, c.b] {
+            buffer.write_all(&v.to_bits().to_le_bytes())?;
+        }
+    }
+    buffer.flush()
+}
+
+/// Writes the beauty as `name("out")` and each pass as `name("out_<pass>")`
+fn write_layers(
+    mut layers: Layers,
+    name: &dyn Fn(&str, &str) -> String,
+    args: &[String],
+    exposure: &Exposure,
+    display: &DisplayTransform,
+) -> std::io::Result<()> {
+    if args.iter().any(|a| a == "--denoise") {
+        let guide = |aov| layers.get(aov).expect("--denoise renders its guide passes");
+        layers.beauty = Denoiser::default().denoise(
+            &layers.beauty,
+            guide(Aov::Albedo),
+            guide(Aov::Normal),
+            guide(Aov::Depth),
+        );
+    }
+    exposure.apply(&mut layers.beauty);
+    let post = post_process_from_args(args);
+    if !post.is_empty() {
+        layers.beauty = post.apply(&layers.beauty);
+    }
+    let beauty = crop_from_args(layers.beauty, args);
+    write_ppm(&name("out", "ppm"), &beauty, &|c| display.bytes(c))?;
+
+    let plain = DisplayTransform {
+        tone_mapper: ToneMapper::Clamp,
+        transfer: Transfer::Srgb,
+    };
+    for (aov, mut film) in layers.aovs {
+        let prefix = format!("out_{}", aov.name());
+        if aov.is_radiance() {
+            exposure.apply(&mut film);
+        }
+        let film = crop_from_args(film, args);
+        match aov {
+            Aov::Depth | Aov::Position => write_pfm(&name(&prefix, "pfm"), &film)?,
+            Aov::Normal => write_ppm(&name(&prefix, "ppm"), &film, &|c| {
+                plain.bytes(&((*c + PURE_COLOR) * 0.5))
+            })?,
+            _ if aov.is_radiance() => write_ppm(&name(&prefix, "ppm"), &film, &|c| display.bytes(c))?,
+            _ => write_ppm(&name(&prefix, "ppm"), &film, &|c| plain.bytes(c))?,
+        }
+    }
+    Ok(())
+}
+
+fn arg_str<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let at = args.iter().position(|a| a == name)?;
+    let value = args.get(at + 1).expect("Missing value for argument");
+    Some(value)
+}
+
+fn arg_value(args: &[String], name: &str) -> Option<f32> {
+    arg_str(args, name).map(|v| v.parse().expect("Argument values must be numbers"))
+}
+
+fn aperture_from_args(args: &[String]) -> Aperture {
+    if let Some(path) = arg_str(args, "--aperture-mask") {
+        let mask = ApertureMask::open(path)
+            .unwrap_or_else(|e| panic!("Could not use aperture mask {}: {}", path, e));
+        Aperture::Mask(Arc::new(mask))
+    } else if let Some(blades) = arg_value(args, "--blades") {
+        assert!(blades >= 3.0, "--blades needs at least 3 blades");
+        Aperture::Polygon {
+            blades: blades as u32,
+            rotation: arg_value(args, "--blade-rotation").unwrap_or(0.0),
+        }
+    } else {
+        Aperture::Circle
+    }
+}
+
+fn shutter_curve_from_args(args: &[String]) -> ShutterCurve {
+    match arg_str(args, "--shutter-curve") {
+        None | Some("box") => ShutterCurve::Box,
+        Some("triangle") => ShutterCurve::Triangle,
+        Some(values) => ShutterCurve::Custom(CurveTable::new(
+            values
+                .split(',')
+                .map(|v| v.parse().expect("--shutter-curve takes box, triangle or numbers"))
+                .collect(),
+        )),
+    }
+}
+
+fn camera_from_args(builder: &CameraBuilder, args: &[String]) -> Box<dyn CameraModel> {
+    let layout = if args.iter().any(|a| a == "--over-under") {
+        StereoLayout::OverUnder
+    } else {
+        StereoLayout::SideBySide
+    };
+    let stereo = arg_value(args, "--stereo");
+    if let Some(view_width) = arg_value(args, "--ortho") {
+        Box::new(builder.build_orthographic(view_width))
+    } else if let Some(fov) = arg_value(args, "--fisheye") {
+        Box::new(builder.build_fisheye(fov))
+    } else if args.iter().any(|a| a == "--equirectangular") {
+        match stereo {
+            Some(ipd) => Box::new(builder.build_equirectangular_stereo(ipd, layout)),
+            None => Box::new(builder.build_equirectangular()),
+        }
+    } else if let Some(ipd) = stereo {
+        let convergence = arg_value(args, "--convergence");
+        Box::new(builder.build_stereo(ipd, convergence, layout))
+    } else {
+        Box::new(builder.build())
+    }
+}
+
+fn display_from_args(args: &[String]) -> DisplayTransform {
+    let tone_mapper = match arg_str(args, "--tonemap") {
+        None | Some("clamp") => ToneMapper::Clamp,
+        Some("reinhard") => ToneMapper::Reinhard,
+        Some("extended-reinhard") => ToneMapper::ExtendedReinhard {
+            white: arg_value(args, "--white").unwrap_or(4.0),
         },
-        Point {
-            x: 0.0,
-            y: 0.5,
-            z: 0.0,
+        Some("hable") => ToneMapper::Hable,
+        Some("aces") => ToneMapper::Aces,
+        Some(other) => panic!("Unknown tone mapper {}", other),
+    };
+    let transfer = if args.iter().any(|a| a == "--gamma2") {
+        Transfer::Gamma2
+    } else {
+        Transfer::Srgb
+    };
+    DisplayTransform {
+        tone_mapper,
+        transfer,
+    }
+}
+
+fn filter_from_args(args: &[String]) -> Filter {
+    match arg_str(args, "--filter") {
+        None | Some("box") => Filter::box_filter(),
+        Some("tent") => Filter::tent(),
+        Some("gaussian") => Filter::gaussian(),
+        Some("mitchell") => Filter::mitchell(),
+        Some(other) => panic!("Unknown filter {}", other),
+    }
+}
+
+/// `--crop X Y WIDTH HEIGHT` in pixels from the bottom left
+fn crop_from_args(film: Film, args: &[String]) -> Film {
+    match args.iter().position(|a| a == "--crop") {
+        Some(at) => {
+            let num = |i: usize| -> usize {
+                args.get(at + i)
+                    .and_then(|a| a.parse().ok())
+                    .expect("--crop takes X Y WIDTH HEIGHT")
+            };
+            film.crop(num(1), num(2), num(3), num(4))
+        }
+        None => film,
+    }
+}
+
+/// `--aovs` for every pass or `--aovs albedo,normal,depth` for some
+fn aovs_from_args(args: &[String]) -> Vec<Aov> {
+    match args.iter().position(|a| a == "--aovs") {
+        Some(at) => match args.get(at + 1).filter(|a| !a.starts_with("--")) {
+            Some(names) => names
+                .split(',')
+                .map(|n| Aov::from_name(n).expect("Unknown pass"))
+                .collect(),
+            None => ALL_AOVS.to_vec(),
         },
-        Point {
-            x: 0.0,
-            y: 1.0,
-            z: 0.0,
+        None => vec![],
+    }
+}
+
+/// `--clamp-indirect MAX` and `--reject-outliers [SIGMAS]`, both off by default
+fn fireflies_from_args(args: &[String]) -> Fireflies {
+    let reject = args.iter().position(|a| a == "--reject-outliers").map(|at| {
+        args.get(at + 1)
+            .filter(|a| !a.starts_with("--"))
+            .map(|a| a.parse().expect("--reject-outliers takes a number"))
+            .unwrap_or(3.0)
+    });
+    Fireflies {
+        max_indirect: arg_value(args, "--clamp-indirect"),
+        reject_sigmas: reject,
+    }
+}
+
+/// `--bloom THRESHOLD` with `--bloom-radius` and `--bloom-strength`,
+/// `--vignette AMOUNT` and `--chromatic-aberration AMOUNT`
+fn post_process_from_args(args: &[String]) -> PostProcess {
+    let defaults = Bloom::default();
+    let radius = arg_value(args, "--bloom-radius").unwrap_or(defaults.radius);
+    assert!(radius > 0.0, "--bloom-radius must be more than 0");
+    PostProcess {
+        bloom: arg_value(args, "--bloom").map(|threshold| Bloom {
+            threshold,
+            radius,
+            strength: arg_value(args, "--bloom-strength").unwrap_or(defaults.strength),
+        }),
+        vignette: arg_value(args, "--vignette"),
+        chromatic_aberration: arg_value(args, "--chromatic-aberration"),
+    }
+}
+
+/// `--spectral` with `d65` (the default), `a`, `e` or a blackbody
+/// temperature in kelvin for the light
+fn spectral_from_args(args: &[String]) -> Option<Emission> {
+    let at = args.iter().position(|a| a == "--spectral")?;
+    let illuminant = match args.get(at + 1).filter(|a| !a.starts_with("--")) {
+        None => Illuminant::D65,
+        Some(name) => match name.as_str() {
+            "d65" => Illuminant::D65,
+            "a" => Illuminant::A,
+            "e" => Illuminant::E,
+            kelvin => Illuminant::Blackbody {
+                kelvin: kelvin.parse().expect("Unknown illuminant"),
+            },
         },
-        15.0,
-        NX as f32 / NY as f32,
-        0.01,
-        0.0,
-        1.0,
-    );
+    };
+    Some(Emission::new(illuminant))
+}
+
+/// The passes asked for plus the ones `--denoise` needs to find edges
+fn guide_aovs(mut aovs: Vec<Aov>, args: &[String]) -> Vec<Aov> {
+    if args.iter().any(|a| a == "--denoise") {
+        for guide in &[Aov::Albedo, Aov::Normal, Aov::Depth] {
+            if !aovs.contains(guide) {
+                aovs.push(*guide);
+            }
+        }
+    }
+    aovs
+}
+
+/// `--frames FIRST LAST [FPS] [SHUTTER_ANGLE]` renders a turntable sequence
+fn frame_range_from_args(args: &[String]) -> Option<FrameRange> {
+    let at = args.iter().position(|a| a == "--frames")?;
+    let values: Vec<f32> = args[at + 1..]
+        .iter()
+        .take_while(|a| !a.starts_with("--"))
+        .map(|a| a.parse().expect("--frames takes numbers"))
+        .collect();
+    let num = |i: usize, default: f32| values.get(i).cloned().unwrap_or(default);
+    Some(FrameRange {
+        first: num(0, 0.0) as i32,
+        last: num(1, 0.0) as i32,
+        fps: num(2, 24.0),
+        shutter_angle: num(3, 180.0),
+    })
+}
+
+fn main() -> std::io::Result<()> {
+    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().collect();
+
+    let look_from = Point {
+        x: 13.0,
+        y: 2.0,
+        z: 3.0,
+    };
+    let look_at = Point {
+        x: 0.0,
+        y: 0.5,
+        z: 0.0,
+    };
 
     //let spherelist = get_spheres_many();
+    //let spherelist = get_material_spheres();
     let spherelist = get_old_spheres();
 
     let bound_box = spheres_to_bounding_box(spherelist.spheres.clone());
+    println!("Built boxes");
 
-    let mut to_calc = vec![];
+    let defaults = Exposure::default();
+    let focal_length = arg_value(&args, "--focal-length").unwrap_or(0.05);
+    // One opening drives both the depth of field and the exposure
+    let aperture = match arg_value(&args, "--f-stop") {
+        Some(f_stop) => focal_length / f_stop,
+        None => arg_value(&args, "--aperture").unwrap_or(focal_length / defaults.f_stop),
+    };
+    let mut builder = CameraBuilder::new()
+        .look_from(look_from)
+        .look_at(look_at)
+        .vfov(15.0)
+        .aspect(NX as f32 / NY as f32)
+        .aperture(aperture)
+        .aperture_shape(aperture_from_args(&args))
+        .shutter_curve(shutter_curve_from_args(&args));
+    if let Some(readout) = arg_value(&args, "--rolling-shutter") {
+        builder = builder.rolling_shutter(readout);
+    }
+    // A pinhole has no f-number, it is exposed like the default lens
+    if aperture > 0.0 {
+        builder = builder.lens(focal_length, focal_length / aperture);
+    }
+    let exposure = builder.exposure(
+        arg_value(&args, "--iso").unwrap_or(defaults.iso),
+        arg_value(&args, "--shutter-speed").unwrap_or(defaults.shutter_speed),
+        arg_value(&args, "--ev").unwrap_or(defaults.compensation),
+    );
+    let display = display_from_args(&args);
+    let aovs = guide_aovs(aovs_from_args(&args), &args);
+    let samples = arg_value(&args, "--samples").map_or(NS, |n| n as i32);
+    assert!(samples > 0, "--samples needs at least 1 sample per pixel");
+    let sampling = Sampling {
+        samples,
+        filter: filter_from_args(&args),
+        fireflies: fireflies_from_args(&args),
+        spectral: spectral_from_args(&args),
+    };
 
-    for j in (0..NY - 1).rev() {
-        for i in 0..NX {
-            to_calc.push((i, j, &cam));
+    match frame_range_from_args(&args) {
+        Some(range) => {
+            let start = range.first as f32 / range.fps;
+            let track = CameraTrack::turntable(
+                look_from,
+                look_at,
+                15.0,
+                aperture,
+                start,
+                start + range.duration(),
+            );
+            for frame in range.first..=range.last {
+                let cam = camera_from_args(&track.builder(&builder, range.shutter(frame)), &args);
+                let layers = render(&*cam, &bound_box, &aovs, &sampling);
+                let name = |prefix: &str, extension: &str| range.file_name(prefix, frame, extension);
+                write_layers(layers, &name, &args, &exposure, &display)?;
+                println!("Wrote frame {}", frame);
+            }
+        }
+        None => {
+            let cam = camera_from_args(&builder, &args);
+            let layers = render(&*cam, &bound_box, &aovs, &sampling);
+            let name = |prefix: &str, extension: &str| format!("{}.{}", prefix, extension);
+            write_layers(layers, &name, &args, &exposure, &display)?;
         }
     }
-    println!("Built boxes");
 
-    // Do fancy thread local storage of the BVH boxes
-    thread_local!(static STORE: RefCell<Option<BvhBox>> = RefCell::new(None));
+    Ok(())
+}
 
-    let pixels: Vec<Color> = to_calc
-        .par_iter()
-        .map(|a| {
-            STORE.with(|bvh| {
-                let mut local_bvh = bvh.borrow_mut();
-                if local_bvh.is_none() {
-                    *local_bvh = Some(bound_box.clone());
-                }
-                calc_pixel(a, local_bvh.as_mut().unwrap())
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    /// Two mirrors, one above and behind the other. Mirrors scatter without
+    /// using any randomness so every path through them is the same.
+    #[allow(dead_code)]
+    fn mirrors() -> BvhBox {
+        let mirror = |y: f32, z: f32, radius: f32| {
+            SphereThing::S(Sphere {
+                center: Point { x: 0.0, y, z },
+                radius,
+                material: Material::Metal(Metal {
+                    albedo: Color {
+                        r: 0.9,
+                        g: 0.6,
+                        b: 0.3,
+                    },
+                    fuzz: 0.0,
+                }),
             })
-        }).collect();
+        };
+        spheres_to_bounding_box(vec![mirror(0.0, -3.0, 1.0), mirror(2.5, -2.0, 1.0)])
+    }
 
-    for row in pixels {
-        buffer.write_all(row.as_color_str().as_bytes())?;
+    #[allow(dead_code)]
+    fn towards(y: f32) -> Ray {
+        Ray {
+            origin: Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            direction: Point { x: 0.0, y, z: -1.0 },
+            time: 0.0,
+            wavelength: 0.0,
+        }
     }
-    buffer.flush()?;
 
-    Ok(())
+    #[test]
+    fn test_light_passes_add_up_to_the_beauty() {
+        let world = mirrors();
+        let fireflies = Fireflies::default();
+        // Straight back out to the sky, then off the lower mirror into the
+        // upper one
+        for (y, bounces) in &[(0.0, false), (0.2, true)] {
+            let r = towards(*y);
+            let sample = color_with_aovs(&r, &world, &fireflies);
+            assert_eq!(sample.indirect.length() > 0.0, *bounces, "ray towards {}", y);
+            let beauty = color(&r, &world, 0, &fireflies);
+            assert!((sample.direct + sample.indirect - beauty).length() < 1e-5);
+
+            let emission = Emission::new(Illuminant::D65);
+            let wavelengths = SampledWavelengths::sample(0.3);
+            let spectral = spectral_with_aovs(&r, &world, &fireflies, &emission, &wavelengths);
+            let beauty = color_spectral(&r, &world, 0, &fireflies, &emission, &wavelengths);
+            let beauty = beauty.to_rgb(&wavelengths);
+            assert!((spectral.direct + spectral.indirect - beauty).length() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_first_hit_passes() {
+        let world = mirrors();
+        let r = towards(0.0);
+        let sample = color_with_aovs(&r, &world, &Fireflies::default());
+        assert!((sample.depth - 2.0).abs() < 1e-4);
+        assert!((sample.normal.z - 1.0).abs() < 1e-4);
+        assert!((sample.position.z + 2.0).abs() < 1e-4);
+        assert!((sample.albedo.r - 0.9).abs() < 1e-5);
+        assert!((sample.material_id - id_color(0)).length() < 1e-6);
+        let miss = color_with_aovs(&towards(-5.0), &world, &Fireflies::default());
+        assert_eq!(miss.depth, 0.0);
+        assert!((miss.direct - sky(&towards(-5.0))).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_frames_stops_at_the_next_flag() {
+        let range = frame_range_from_args(&args("ray --frames 0 47 --ortho 8")).unwrap();
+        assert_eq!((range.first, range.last), (0, 47));
+        assert_eq!((range.fps, range.shutter_angle), (24.0, 180.0));
+
+        let range = frame_range_from_args(&args("ray --frames 2 9 30 90 --denoise")).unwrap();
+        assert_eq!((range.first, range.last), (2, 9));
+        assert_eq!((range.fps, range.shutter_angle), (30.0, 90.0));
+        assert!(frame_range_from_args(&args("ray --denoise")).is_none());
+    }
 }